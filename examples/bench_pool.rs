@@ -0,0 +1,85 @@
+//! Compares opening a connection per lookup against reusing pooled connections.
+//!
+//! Run `examples/stress_insert.rs` first to fill the database, then
+//! `cargo run --release --example bench_pool`. Timings depend on the machine and the
+//! size of the database, so only compare them within the same run.
+use rand::{Rng, SeedableRng};
+use rusqlite::OpenFlags;
+use std::time::{Duration, Instant};
+use tg::sql;
+
+const LOOKUPS: usize = 100_000;
+
+fn lookup(db: &rusqlite::Connection, tag: &str, file: &str) -> rusqlite::Result<bool> {
+    let tag = db.prepare_cached(sql::TAG_EXISTS)?.exists([tag])?;
+    let file = db.prepare_cached(sql::FILE_EXISTS)?.exists([file])?;
+    Ok(tag && file)
+}
+
+/// Runs `LOOKUPS` lookups split across `threads`, returning the elapsed time.
+fn run(
+    threads: usize,
+    names: &[(String, String)],
+    lookup: impl Fn(&str, &str) -> rusqlite::Result<bool> + Sync,
+) -> anyhow::Result<Duration> {
+    let start = Instant::now();
+    let lookup = &lookup;
+    std::thread::scope(|s| {
+        let handles = names
+            .chunks(names.len().div_ceil(threads))
+            .map(|chunk| {
+                s.spawn(move || {
+                    for (tag, file) in chunk {
+                        lookup(tag, file)?;
+                    }
+                    Ok::<_, rusqlite::Error>(())
+                })
+            })
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .try_for_each(|h| h.join().expect("lookup thread panicked"))
+            .map_err(anyhow::Error::from)
+    })?;
+    Ok(start.elapsed())
+}
+
+fn main() -> anyhow::Result<()> {
+    let config = tg::config::Config::load(None)?;
+    let db_path = config.db_path().to_path_buf();
+    let pool = tg::pool::Pool::new(&db_path);
+
+    let mut rng = rand::rngs::StdRng::seed_from_u64(57);
+    let names = (0..LOOKUPS)
+        .map(|_| {
+            let tag = format!("t{}", rng.gen_range(1..=1000));
+            let file = format!("f{}", rng.gen_range(0..5_000_000));
+            (tag, file)
+        })
+        .collect::<Vec<_>>();
+
+    let fresh = |tag: &str, file: &str| {
+        let db = rusqlite::Connection::open_with_flags(
+            &db_path,
+            OpenFlags::SQLITE_OPEN_NO_MUTEX | OpenFlags::SQLITE_OPEN_READ_ONLY,
+        )?;
+        lookup(&db, tag, file)
+    };
+    let pooled = |tag: &str, file: &str| {
+        let db = pool.read()?;
+        lookup(&db, tag, file)
+    };
+
+    let mut counts = vec![1, std::thread::available_parallelism()?.get()];
+    counts.dedup();
+    for threads in counts {
+        let fresh = run(threads, &names, fresh)?;
+        let pooled = run(threads, &names, pooled)?;
+        println!(
+            "{LOOKUPS} lookups with {threads} threads: fresh connection {fresh:?}, pooled {pooled:?} ({:.1}x)",
+            fresh.as_secs_f64() / pooled.as_secs_f64()
+        );
+    }
+
+    Ok(())
+}
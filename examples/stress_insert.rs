@@ -4,10 +4,10 @@ use rusqlite::params;
 use std::time::Instant;
 
 fn main() -> anyhow::Result<()> {
-    let config = tg::config::Config::load()?;
+    let config = tg::config::Config::load(None)?;
     let db_path = config.db_path().to_path_buf();
     let mut conn = rusqlite::Connection::open(&db_path).context("database creation failed")?;
-    conn.execute_batch(include_str!("../src/sql/migrations.sql"))?;
+    tg::sql::migrate(&mut conn)?;
 
     // Prepare the statements for insertion
     let start = Instant::now();
@@ -19,10 +19,10 @@ fn main() -> anyhow::Result<()> {
         for i in 1..=1000 {
             insert_tag_stmt.execute([format!("t{i}")])?;
         }
-        let mut insert_file_stmt = tx.prepare("INSERT OR IGNORE INTO Files (file) VALUES (?1)")?;
+        let mut insert_file_stmt = tx.prepare("INSERT OR IGNORE INTO Files (path) VALUES (?1)")?;
         let mut insert_filetag_stmt = tx.prepare(
             "INSERT INTO FileTags (fileId, tagId) VALUES (
-            (SELECT id FROM Files WHERE file = ?1),
+            (SELECT id FROM Files WHERE path = ?1),
             ?2
             )",
         )?;
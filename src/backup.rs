@@ -0,0 +1,240 @@
+use anyhow::{bail, Context, Result};
+use log::debug;
+use rusqlite::backup::{Backup, StepResult};
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::sql;
+
+/// How long to wait for the database to stop being written to.
+const TIMEOUT: Duration = Duration::from_secs(10);
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Prefix of the backups taken before migrating, the only ones rotated.
+const MIGRATION_PREFIX: &str = "pre-migration";
+
+/// Directory of the backups of the database at `db_path`, `backups` next to it.
+pub fn dir(db_path: &Path) -> PathBuf {
+    db_path.parent().unwrap_or(Path::new(".")).join("backups")
+}
+
+/// Path of a new backup of the database at `db_path`, as in `backups/PREFIX-UNIX_MILLIS.sqlite`.
+pub fn path(db_path: &Path, prefix: &str) -> PathBuf {
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    dir(db_path).join(format!("{prefix}-{timestamp}.sqlite"))
+}
+
+/// Copies every page of `from` to `to` in a single step, so the copy is consistent
+/// even if another connection is writing to `from`.
+fn copy(from: &rusqlite::Connection, to: &mut rusqlite::Connection) -> Result<()> {
+    let backup = Backup::new(from, to)?;
+    let start = Instant::now();
+    loop {
+        match backup.step(-1)? {
+            StepResult::Done => return Ok(()),
+            _ if start.elapsed() > TIMEOUT => {
+                bail!("the database was still busy after {TIMEOUT:?}")
+            }
+            _ => std::thread::sleep(POLL_INTERVAL),
+        }
+    }
+}
+
+/// Writes a snapshot of `db` to `path` with SQLite's online backup, safe while it's mounted.
+///
+/// The snapshot is written to a temporary file that's renamed once complete.
+pub fn backup(db: &rusqlite::Connection, path: &Path, overwrite: bool) -> Result<()> {
+    if !overwrite && path.try_exists()? {
+        bail!("{path:?} already exists");
+    }
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+
+    debug!("Backing up to {path:?}");
+    let written = rusqlite::Connection::open(&tmp)
+        .map_err(anyhow::Error::from)
+        .and_then(|mut dst| copy(db, &mut dst));
+    if let Err(e) = written {
+        let _ = std::fs::remove_file(&tmp);
+        return Err(e).with_context(|| format!("could not write {tmp:?}"));
+    }
+    std::fs::rename(&tmp, path).with_context(|| format!("could not move {tmp:?} to {path:?}"))
+}
+
+/// Checks that `db` is an intact tg database that this version can open.
+pub fn check(db: &rusqlite::Connection) -> Result<()> {
+    let problems = db
+        .prepare(sql::INTEGRITY_CHECK)?
+        .query_map([], |r| r.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    if problems != ["ok"] {
+        bail!("the integrity check failed:\n{}", problems.join("\n"));
+    }
+    for table in ["Files", "Tags", "FileTags"] {
+        if !sql::has_table(db, table)? {
+            bail!("not a tg database, the {table} table is missing");
+        }
+    }
+    let version = sql::version(db)?;
+    if version > sql::VERSION {
+        bail!(
+            "the database has schema version {version}, newer than the supported {}",
+            sql::VERSION
+        );
+    }
+    Ok(())
+}
+
+/// Replaces the contents of `db` with the backup at `path` after checking it, then migrates it.
+///
+/// If `previous` is provided, the current contents are backed up there before being replaced.
+pub fn restore(db: &mut rusqlite::Connection, path: &Path, previous: Option<&Path>) -> Result<()> {
+    let backup =
+        rusqlite::Connection::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .with_context(|| format!("could not open {path:?}"))?;
+    check(&backup).with_context(|| format!("{path:?} cannot be restored"))?;
+    if let Some(previous) = previous {
+        self::backup(db, previous, false)?;
+    }
+    copy(&backup, db).with_context(|| format!("could not restore {path:?}"))?;
+    sql::migrate(db)?;
+    Ok(())
+}
+
+/// Removes the oldest backups taken before migrating in `dir`, keeping the last `keep`.
+fn rotate(dir: &Path, keep: usize) -> Result<()> {
+    let mut backups = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_str()
+                .is_some_and(|n| n.starts_with(MIGRATION_PREFIX) && n.ends_with(".sqlite"))
+        })
+        .filter_map(|entry| Some((entry.metadata().ok()?.modified().ok()?, entry.path())))
+        .collect::<Vec<_>>();
+    backups.sort();
+    for (_, path) in backups.iter().rev().skip(keep) {
+        debug!("Removing old backup {path:?}");
+        std::fs::remove_file(path).with_context(|| format!("could not remove {path:?}"))?;
+    }
+    Ok(())
+}
+
+/// Applies the pending migrations of the database at `db_path`,
+/// backing it up first if `keep` is not 0 and keeping the last `keep` of these backups.
+pub fn migrate(db: &mut rusqlite::Connection, db_path: &Path, keep: usize) -> Result<()> {
+    if keep > 0 && sql::needs_migration(db)? {
+        let path = path(db_path, MIGRATION_PREFIX);
+        backup(db, &path, false).context("could not back up the database before migrating it")?;
+        rotate(&dir(db_path), keep)?;
+    }
+    sql::migrate(db)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tg-backup-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Migrated database with `/a` tagged `x`.
+    fn db() -> rusqlite::Connection {
+        let mut db = rusqlite::Connection::open_in_memory().unwrap();
+        sql::migrate(&mut db).unwrap();
+        db.execute(sql::INSERT_FILE, ["/a"]).unwrap();
+        db.execute(sql::INSERT_TAG, ["x"]).unwrap();
+        db.execute(sql::INSERT_FILETAG, ["/a", "x"]).unwrap();
+        db
+    }
+
+    fn files(db: &rusqlite::Connection) -> Vec<String> {
+        crate::db::files(db, &[]).unwrap()
+    }
+
+    #[test]
+    fn backup_and_restore() {
+        let dir = temp_dir("restore");
+        let path = dir.join("backup.sqlite");
+        backup(&db(), &path, false).unwrap();
+        assert!(backup(&db(), &path, false).is_err());
+
+        let mut restored = rusqlite::Connection::open_in_memory().unwrap();
+        restore(&mut restored, &path, None).unwrap();
+        assert_eq!(files(&restored), ["/a"]);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn corrupt_backups_are_refused() {
+        let dir = temp_dir("corrupt");
+        let path = dir.join("corrupt.sqlite");
+        std::fs::write(&path, b"not a database, just some garbage bytes").unwrap();
+
+        let mut db = db();
+        assert!(restore(&mut db, &path, None).is_err());
+        assert_eq!(files(&db), ["/a"]);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn newer_backups_are_refused() {
+        let dir = temp_dir("newer");
+        let path = dir.join("newer.sqlite");
+        let newer = db();
+        newer
+            .pragma_update(None, "user_version", sql::VERSION + 1)
+            .unwrap();
+        backup(&newer, &path, false).unwrap();
+
+        let mut db = rusqlite::Connection::open_in_memory().unwrap();
+        sql::migrate(&mut db).unwrap();
+        let error = restore(&mut db, &path, None).unwrap_err();
+        assert!(format!("{error:#}").contains("newer than the supported"));
+        assert!(files(&db).is_empty());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn rotate_keeps_the_newest() {
+        let dir = temp_dir("rotate");
+        let start = SystemTime::now() - Duration::from_secs(60);
+        for i in 0..5 {
+            let path = dir.join(format!("{MIGRATION_PREFIX}-{i}.sqlite"));
+            let file = std::fs::File::create(path).unwrap();
+            file.set_modified(start + Duration::from_secs(i)).unwrap();
+        }
+        std::fs::write(dir.join("manual.sqlite"), "").unwrap();
+
+        rotate(&dir, 2).unwrap();
+        let mut left = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        left.sort();
+        assert_eq!(
+            left,
+            [
+                "manual.sqlite",
+                "pre-migration-3.sqlite",
+                "pre-migration-4.sqlite"
+            ]
+        );
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,224 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// Thread-safe map whose entries expire after `ttl`, evicting the oldest entry when full.
+///
+/// A `capacity` of 0 disables the cache.
+pub struct Cache<K, V> {
+    entries: Mutex<HashMap<K, (Instant, V)>>,
+    capacity: usize,
+    ttl: Duration,
+    /// Incremented by `clear`, so values computed before it aren't inserted
+    generation: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> Cache<K, V> {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Cache {
+            entries: Mutex::default(),
+            capacity,
+            ttl,
+            generation: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        let entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some((inserted, value)) if inserted.elapsed() < self.ttl => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(value.clone())
+            }
+            _ => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    pub fn insert(&self, key: K, value: V) {
+        self.insert_if(key, value, None);
+    }
+
+    /// Inserts `value` unless the cache was cleared since `generation` was read.
+    fn insert_if(&self, key: K, value: V, generation: Option<u64>) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if generation.is_some_and(|g| g != self.generation.load(Ordering::Relaxed)) {
+            return;
+        }
+        if entries.len() >= self.capacity && !entries.contains_key(&key) {
+            entries.retain(|_, (inserted, _)| inserted.elapsed() < self.ttl);
+        }
+        if entries.len() >= self.capacity && !entries.contains_key(&key) {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, (inserted, _))| *inserted)
+                .map(|(k, _)| k.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(key, (Instant::now(), value));
+    }
+
+    /// Returns the cached value of `key` or inserts the result of `f`.
+    ///
+    /// The result isn't cached if the cache is cleared while running `f`, as it may be outdated.
+    pub fn get_or_try_insert_with<E>(
+        &self,
+        key: K,
+        f: impl FnOnce() -> Result<V, E>,
+    ) -> Result<V, E> {
+        let generation = self.generation.load(Ordering::Relaxed);
+        if let Some(value) = self.get(&key) {
+            return Ok(value);
+        }
+        let value = f()?;
+        self.insert_if(key, value.clone(), Some(generation));
+        Ok(value)
+    }
+
+    /// Removes every entry, used when the database is modified.
+    pub fn clear(&self) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.clear();
+        self.generation.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Stats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl Stats {
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            return 0.0;
+        }
+        self.hits as f64 / total as f64
+    }
+}
+
+impl std::fmt::Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} hits, {} misses ({:.1}% hit rate)",
+            self.hits,
+            self.misses,
+            self.hit_rate() * 100.0
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TTL: Duration = Duration::from_millis(50);
+
+    #[test]
+    fn expires_after_ttl() {
+        let cache = Cache::new(4, TTL);
+        cache.insert("a", 1);
+        assert_eq!(cache.get(&"a"), Some(1));
+        std::thread::sleep(TTL * 2);
+        assert_eq!(cache.get(&"a"), None);
+    }
+
+    #[test]
+    fn evicts_oldest_when_full() {
+        let cache = Cache::new(2, Duration::from_secs(60));
+        for (key, value) in [("a", 1), ("b", 2), ("c", 3)] {
+            cache.insert(key, value);
+            std::thread::sleep(Duration::from_millis(2));
+        }
+        assert_eq!(cache.get(&"a"), None);
+        assert_eq!(cache.get(&"b"), Some(2));
+        assert_eq!(cache.get(&"c"), Some(3));
+
+        // Replacing an entry doesn't evict another one
+        cache.insert("b", 4);
+        assert_eq!(cache.get(&"b"), Some(4));
+        assert_eq!(cache.get(&"c"), Some(3));
+    }
+
+    #[test]
+    fn evicts_expired_before_oldest() {
+        let cache = Cache::new(2, TTL);
+        cache.insert("a", 1);
+        std::thread::sleep(TTL * 2);
+        cache.insert("b", 2);
+        cache.insert("c", 3);
+        assert_eq!(cache.get(&"b"), Some(2));
+        assert_eq!(cache.get(&"c"), Some(3));
+    }
+
+    #[test]
+    fn zero_capacity_disables() {
+        let cache = Cache::new(0, TTL);
+        cache.insert("a", 1);
+        assert_eq!(cache.get(&"a"), None);
+    }
+
+    #[test]
+    fn counts_hits_and_misses() {
+        let cache = Cache::new(4, Duration::from_secs(60));
+        let mut calls = 0;
+        for _ in 0..3 {
+            let value = cache.get_or_try_insert_with("a", || {
+                calls += 1;
+                Ok::<_, ()>(1)
+            });
+            assert_eq!(value, Ok(1));
+        }
+        assert_eq!(calls, 1);
+        assert_eq!(
+            cache.get_or_try_insert_with("b", || Err("failed")),
+            Err("failed")
+        );
+        assert_eq!(cache.get(&"b"), None);
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (2, 3));
+        assert_eq!(stats.hit_rate(), 0.4);
+    }
+
+    #[test]
+    fn skips_values_computed_before_clear() {
+        let cache = Cache::new(4, Duration::from_secs(60));
+        let value = cache.get_or_try_insert_with("a", || {
+            cache.clear();
+            Ok::<_, ()>(1)
+        });
+        assert_eq!(value, Ok(1));
+        assert_eq!(cache.get(&"a"), None);
+
+        assert_eq!(cache.get_or_try_insert_with("a", || Ok::<_, ()>(2)), Ok(2));
+        assert_eq!(cache.get(&"a"), Some(2));
+    }
+}
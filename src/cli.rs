@@ -2,8 +2,29 @@ use std::path::PathBuf;
 
 use bpaf::Bpaf;
 
+use crate::{
+    config::MountOptions,
+    export::Format,
+    import::{Mode, Options, Rewrite},
+};
+
 #[derive(Bpaf, Debug, Clone)]
 #[bpaf(options, generate(parse))]
+pub struct Args {
+    /// Use the database, mountpoint and prefixes of profile NAME instead of the default ones
+    #[bpaf(short, long, argument("NAME"))]
+    pub profile: Option<String>,
+    /// Use the config file at PATH, can also be set with TG_CONFIG
+    #[bpaf(long, env("TG_CONFIG"), argument("PATH"))]
+    pub config: Option<PathBuf>,
+    /// Use the database at PATH, can also be set with TG_DB
+    #[bpaf(long, env("TG_DB"), argument("PATH"))]
+    pub db: Option<PathBuf>,
+    #[bpaf(external)]
+    pub cli: Cli,
+}
+
+#[derive(Bpaf, Debug, Clone)]
 pub enum Cli {
     /// Adds TAGS to FILE, will create the tags that don't exist.
     ///
@@ -11,26 +32,171 @@ pub enum Cli {
     // #[bpaf(command)]
     Add {
         /// Optional name that will be displayed instead of the real one
-        name: String,
-        
+        #[bpaf(argument("NAME"))]
+        name: Option<String>,
+
+        /// Also add the tags to the 'user.xdg.tags' extended attribute of FILE
+        xattr: bool,
+
         #[bpaf(positional("FILE"))]
         file: PathBuf,
         #[bpaf(positional("TAGS"))]
         tags: Vec<String>,
     },
+    /// Lists the files tagged with all TAGS, or every file if skipped
+    ///
+    /// Uses the database of the project containing the current directory, if any.
+    /// Example: 'tg ls rust config'
+    #[bpaf(command)]
+    Ls {
+        #[bpaf(positional("TAGS"))]
+        tags: Vec<String>,
+    },
+    /// Creates a project database in DIR/.tg, or in the current directory if skipped
+    ///
+    /// Files added inside DIR are stored in it with paths relative to DIR.
+    /// Example: 'tg init ~/Projects/tg'
+    #[bpaf(command)]
+    Init {
+        #[bpaf(positional("DIR"), optional)]
+        dir: Option<PathBuf>,
+    },
     /// Mounts the filesystem to the specified MOUNTPOINT or the previous one if skipped
     ///
     /// Example: 'tg mount ~/Tags'
     #[bpaf(command)]
     Mount {
+        /// Detach from the terminal, logging to 'tg.log' in the data directory
+        daemon: bool,
+        /// Mount every profile with a mountpoint at the same time
+        all: bool,
+        #[bpaf(external)]
+        mount_flags: MountFlags,
+        #[bpaf(positional("MOUNTPOINT"), optional)]
+        mountpoint: Option<PathBuf>,
+    },
+    /// Unmounts the filesystem from MOUNTPOINT or the one currently mounted if skipped
+    ///
+    /// Example: 'tg unmount'
+    #[bpaf(command)]
+    Unmount {
         #[bpaf(positional("MOUNTPOINT"), optional)]
         mountpoint: Option<PathBuf>,
     },
+    /// Shows whether the filesystem is mounted, where and which database it uses
+    #[bpaf(command)]
+    Status,
+    /// Lists the created profiles
+    ///
+    /// Example: 'tg --profile work mount ~/Work' creates the profile 'work'
+    #[bpaf(command)]
+    Profiles,
+    /// Adds the 'user.xdg.tags' extended attribute of every file under DIR to the database
+    ///
+    /// Example: 'tg import-xattrs ~/Documents'
+    #[bpaf(command)]
+    ImportXattrs {
+        #[bpaf(positional("DIR"))]
+        dir: PathBuf,
+    },
+    /// Adds the tags of every file under DIR to its 'user.xdg.tags' extended attribute
+    ///
+    /// Example: 'tg export-xattrs ~/Documents'
+    #[bpaf(command)]
+    ExportXattrs {
+        #[bpaf(positional("DIR"))]
+        dir: PathBuf,
+    },
     #[bpaf(command)]
     Set {
         #[bpaf(external)]
         set: Set,
     },
+    /// Shows and modifies the config of the current profile
+    ///
+    /// Example: 'tg config set mount.read_only true'
+    #[bpaf(command("config"))]
+    Config {
+        #[bpaf(external)]
+        config_command: ConfigCommand,
+    },
+    /// Writes the files and their tags as JSON or CSV to stdout or OUTPUT
+    ///
+    /// Example: 'tg export --format csv --tag rust -o rust.csv'
+    #[bpaf(command)]
+    Export {
+        /// Output format, json or csv
+        #[bpaf(argument("FORMAT"), fallback(Format::Json), display_fallback)]
+        format: Format,
+        #[bpaf(short, long, argument("OUTPUT"))]
+        output: Option<PathBuf>,
+        /// Only export files with all these tags
+        #[bpaf(long("tag"), argument("TAG"))]
+        tags: Vec<String>,
+        /// Only export files inside DIR
+        #[bpaf(argument("DIR"))]
+        under: Option<PathBuf>,
+    },
+    /// Reads the files and their tags from a JSON or CSV export in INPUT or stdin
+    ///
+    /// Example: 'tg import --mode replace --rewrite /old/home=/home/new --dry-run tags.json'
+    #[bpaf(command)]
+    Import {
+        /// Input format, json or csv, guessed from the extension of INPUT if not set
+        #[bpaf(argument("FORMAT"))]
+        format: Option<Format>,
+        #[bpaf(external)]
+        import_flags: ImportFlags,
+        #[bpaf(positional("INPUT"))]
+        input: Option<PathBuf>,
+    },
+    /// Imports the files, tags and values of a TMSU database, the one TMSU would use by default
+    ///
+    /// Values become 'tag=value' tags next to 'tag', and implied tags are added to the files.
+    /// Example: 'tg import-tmsu --dry-run ~/.tmsu/default.db'
+    #[bpaf(command)]
+    ImportTmsu {
+        #[bpaf(external)]
+        import_flags: ImportFlags,
+        #[bpaf(positional("DB"))]
+        database: Option<PathBuf>,
+    },
+    /// Writes a snapshot of the database to OUTPUT, safe while it's mounted
+    ///
+    /// Defaults to a new file in the 'backups' directory next to the database.
+    /// Example: 'tg backup ~/tags.sqlite'
+    #[bpaf(command)]
+    Backup {
+        /// Overwrite OUTPUT if it exists
+        force: bool,
+        #[bpaf(positional("OUTPUT"))]
+        output: Option<PathBuf>,
+    },
+    /// Replaces the database with BACKUP after checking its integrity
+    ///
+    /// The current database is backed up first to the 'backups' directory next to it.
+    /// Example: 'tg restore ~/tags.sqlite'
+    #[bpaf(command)]
+    Restore {
+        #[bpaf(positional("BACKUP"))]
+        backup: PathBuf,
+    },
+    /// Manages the directories that file paths are stored relative to
+    ///
+    /// Example: 'tg root add home ~' and after moving it 'tg root set home /home/new'
+    #[bpaf(command)]
+    Root {
+        #[bpaf(external)]
+        root: Root,
+    },
+    /// Manages the systemd user service that mounts the filesystem on login
+    ///
+    /// Example: 'tg service install'
+    #[bpaf(command)]
+    Service {
+        #[bpaf(external)]
+        service: Service,
+    },
 }
 
 #[derive(Bpaf, Debug, Clone)]
@@ -45,4 +211,164 @@ pub enum Set {
         #[bpaf(positional("VALUE"))]
         value: String,
     },
+    /// Directory where files created inside the mount are stored
+    #[bpaf(command)]
+    Inbox {
+        #[bpaf(positional("DIR"))]
+        value: PathBuf,
+    },
+}
+
+#[derive(Bpaf, Debug, Clone)]
+pub enum ConfigCommand {
+    /// Prints the whole config as TOML
+    #[bpaf(command)]
+    Show,
+    /// Prints the value of KEY, failing if it's not set
+    #[bpaf(command)]
+    Get {
+        #[bpaf(positional("KEY"))]
+        key: String,
+    },
+    /// Sets KEY to VALUE, checking that it has the right type
+    #[bpaf(command)]
+    Set {
+        #[bpaf(positional("KEY"))]
+        key: String,
+        #[bpaf(positional("VALUE"))]
+        value: String,
+    },
+    /// Prints the path of the config file
+    #[bpaf(command)]
+    Path,
+    /// Restores the default value of KEY, or of the whole config if skipped
+    #[bpaf(command)]
+    Reset {
+        #[bpaf(positional("KEY"), optional)]
+        key: Option<String>,
+    },
+    /// Lists every key and its type
+    #[bpaf(command)]
+    Keys,
+}
+
+#[derive(Bpaf, Debug, Clone)]
+pub enum Root {
+    /// Adds the root NAME at DIR, storing the paths of the files inside relative to it
+    #[bpaf(command)]
+    Add {
+        #[bpaf(positional("NAME"))]
+        name: String,
+        #[bpaf(positional("DIR"))]
+        dir: PathBuf,
+    },
+    /// Points the root NAME to DIR, where its files are now found
+    #[bpaf(command)]
+    Set {
+        #[bpaf(positional("NAME"))]
+        name: String,
+        #[bpaf(positional("DIR"))]
+        dir: PathBuf,
+    },
+    /// Removes the root NAME, storing the absolute paths of its files
+    #[bpaf(command)]
+    Rm {
+        #[bpaf(positional("NAME"))]
+        name: String,
+    },
+    /// Lists the roots and their directories
+    #[bpaf(command)]
+    Ls,
+}
+
+#[derive(Bpaf, Debug, Clone)]
+pub enum Service {
+    /// Writes the unit mounting to the configured mountpoint, enabling and starting it
+    #[bpaf(command)]
+    Install {
+        /// Only write the unit file
+        no_enable: bool,
+    },
+    /// Stops, disables and removes the unit
+    #[bpaf(command)]
+    Uninstall,
+    /// Shows whether the unit is installed and running
+    #[bpaf(command)]
+    Status,
+}
+
+/// How the import commands merge the imported files.
+#[derive(Bpaf, Debug, Clone)]
+pub struct ImportFlags {
+    /// add keeps the current tags, replace sets the tags of the imported files
+    /// and mirror also removes the files and tags that weren't imported
+    #[bpaf(argument("MODE"), fallback(Mode::Add), display_fallback)]
+    mode: Mode,
+    /// Replaces the FROM prefix of the imported paths with TO, the first matching one is used
+    #[bpaf(long("rewrite"), argument("FROM=TO"))]
+    rewrites: Vec<Rewrite>,
+    /// Prints the changes without applying them
+    dry_run: bool,
+}
+
+impl ImportFlags {
+    pub fn options(self) -> Options {
+        Options {
+            mode: self.mode,
+            rewrites: self.rewrites,
+            dry_run: self.dry_run,
+        }
+    }
+}
+
+/// Mount options overriding the ones stored in the config for this mount.
+#[derive(Bpaf, Debug, Clone)]
+pub struct MountFlags {
+    /// Show files as regular files instead of symlinks, needed for the 'user.tg.*' attributes
+    passthrough: bool,
+    /// Deny any modification through the mount
+    read_only: bool,
+    /// Allow other users to access the mount
+    allow_other: bool,
+    /// Let the kernel check permissions
+    default_permissions: bool,
+    /// Unmount automatically when the process exits
+    auto_unmount: bool,
+    /// Filesystem name shown by 'mount'
+    #[bpaf(argument("NAME"))]
+    fsname: Option<String>,
+    /// Filesystem subtype shown by 'mount'
+    #[bpaf(argument("NAME"))]
+    subtype: Option<String>,
+    /// Seconds the kernel caches file attributes
+    #[bpaf(argument("SECS"))]
+    attr_ttl: Option<u64>,
+    /// Seconds the kernel caches new entries
+    #[bpaf(argument("SECS"))]
+    entry_ttl: Option<u64>,
+    /// Cap both TTLs to 250ms to show changes made by other processes quickly
+    watch: bool,
+}
+
+impl MountFlags {
+    pub fn apply(&self, options: &mut MountOptions) {
+        options.passthrough |= self.passthrough;
+        options.read_only |= self.read_only;
+        options.allow_other |= self.allow_other;
+        options.default_permissions |= self.default_permissions;
+        options.auto_unmount |= self.auto_unmount;
+        options.watch |= self.watch;
+        if let Some(fsname) = &self.fsname {
+            options.fsname.clone_from(fsname);
+        }
+        if let Some(subtype) = &self.subtype {
+            options.subtype.clone_from(subtype);
+        }
+        if let Some(attr_ttl) = self.attr_ttl {
+            options.attr_ttl = attr_ttl;
+        }
+        if let Some(entry_ttl) = self.entry_ttl {
+            options.entry_ttl = entry_ttl;
+        }
+    }
 }
@@ -1,5 +1,5 @@
 use crate::or_panic;
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use std::path::{Path, PathBuf};
 
 #[derive(serde::Deserialize, serde::Serialize)]
@@ -7,20 +7,165 @@ pub struct Config {
     mountpoint: Option<PathBuf>,
     tag_prefix: String,
     file_prefix: String,
+    /// Directory where files created inside the mount are stored
+    inbox: Option<PathBuf>,
+    #[serde(default)]
+    mount: MountOptions,
+    /// Number of backups taken before migrating the database that are kept, 0 disables them
+    #[serde(default)]
+    backups: usize,
 
+    #[serde(skip)]
+    profile: Option<String>,
     #[serde(skip)]
     config_path: PathBuf,
     #[serde(skip)]
+    data_dir: PathBuf,
+    #[serde(skip)]
     db_path: PathBuf,
 }
 
+/// Prefixes telling tags and files apart inside the mount.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Prefixes {
+    pub tag: String,
+    pub file: String,
+}
+
+impl Prefixes {
+    /// Checks that every name in the mount is either a tag or a file.
+    pub fn validate(&self) -> Result<()> {
+        for (kind, prefix) in [("tag", &self.tag), ("file", &self.file)] {
+            if prefix.is_empty() {
+                bail!("the {kind} prefix cannot be empty");
+            }
+            if prefix.contains(['/', '\0']) {
+                bail!("the {kind} prefix {prefix:?} cannot contain '/' or NUL");
+            }
+        }
+        if self.tag == self.file {
+            bail!(
+                "the tag and file prefixes cannot be the same ({:?})",
+                self.tag
+            );
+        }
+        if self.tag.starts_with(&self.file) || self.file.starts_with(&self.tag) {
+            bail!(
+                "the tag prefix {:?} and the file prefix {:?} cannot start with each other",
+                self.tag,
+                self.file
+            );
+        }
+        Ok(())
+    }
+
+    /// Reads and validates the prefixes stored in the config file at `path`.
+    pub fn read(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let config: Config = confy::load_path(path)?;
+        let prefixes = config.prefixes();
+        prefixes
+            .validate()
+            .with_context(|| format!("invalid prefixes in {path:?}"))?;
+        Ok(prefixes)
+    }
+}
+
+/// Options used when mounting the filesystem.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+#[serde(default)]
+pub struct MountOptions {
+    /// Show files as regular files instead of symlinks, forwarding reads and writes to the real file.
+    /// Needed for the `user.tg.*` extended attributes, which Linux doesn't allow on symlinks
+    pub passthrough: bool,
+    /// Deny any modification through the mount
+    pub read_only: bool,
+    /// Maximum number of directory listings and lookups kept in memory, 0 disables the cache
+    pub cache_size: usize,
+    /// Seconds before a cached listing or lookup is queried again
+    pub cache_ttl: u64,
+    /// Allow other users to access the mount, requires 'user_allow_other' in /etc/fuse.conf
+    pub allow_other: bool,
+    /// Let the kernel check permissions using the reported file attributes
+    pub default_permissions: bool,
+    /// Unmount automatically when the process exits
+    pub auto_unmount: bool,
+    /// Filesystem name shown by `mount` and `df`
+    pub fsname: String,
+    /// Filesystem subtype shown by `mount`, as in 'fuse.SUBTYPE'
+    pub subtype: String,
+    /// Seconds the kernel caches file attributes
+    pub attr_ttl: u64,
+    /// Seconds the kernel caches new entries created through the mount
+    pub entry_ttl: u64,
+    /// Cap both TTLs to 250ms, so changes made by other processes show up quickly
+    pub watch: bool,
+}
+
+impl MountOptions {
+    /// Checks that the names can be passed to the kernel as mount options.
+    pub fn validate(&self) -> Result<()> {
+        for (key, name) in [("fsname", &self.fsname), ("subtype", &self.subtype)] {
+            if name.is_empty() || name.contains([',', '\0']) {
+                bail!("the {key} {name:?} cannot be empty or contain ',' or NUL");
+            }
+        }
+        Ok(())
+    }
+
+    /// Options passed to the kernel when mounting.
+    pub fn kernel_options(&self) -> Vec<String> {
+        let mut options = vec![
+            format!("fsname={}", self.fsname),
+            format!("subtype={}", self.subtype),
+        ];
+        let flags = [
+            (self.read_only, "ro"),
+            (self.allow_other, "allow_other"),
+            (self.default_permissions, "default_permissions"),
+            (self.auto_unmount, "auto_unmount"),
+        ];
+        options.extend(
+            flags
+                .into_iter()
+                .filter(|(set, _)| *set)
+                .map(|(_, o)| o.into()),
+        );
+        options
+    }
+}
+
+impl Default for MountOptions {
+    fn default() -> Self {
+        Self {
+            passthrough: false,
+            read_only: false,
+            cache_size: 10_000,
+            cache_ttl: 10,
+            allow_other: false,
+            default_permissions: false,
+            auto_unmount: false,
+            fsname: String::from("tg"),
+            subtype: String::from("tg"),
+            attr_ttl: 1,
+            entry_ttl: 1,
+            watch: false,
+        }
+    }
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
             mountpoint: Default::default(),
             tag_prefix: String::from(":"),
             file_prefix: String::from("_"),
+            inbox: Default::default(),
+            mount: MountOptions::default(),
+            backups: 0,
+            profile: None,
             config_path: PathBuf::new(),
+            data_dir: PathBuf::new(),
             db_path: PathBuf::new(),
         }
     }
@@ -28,20 +173,111 @@ impl Default for Config {
 
 type ConfyResult = std::result::Result<(), confy::ConfyError>;
 
+/// Paths replacing the default locations of the config and database, never stored.
+#[derive(Debug, Clone, Default)]
+pub struct Overrides {
+    pub config: Option<PathBuf>,
+    pub db: Option<PathBuf>,
+}
+
+impl Overrides {
+    /// Reads the overrides from the `TG_CONFIG` and `TG_DB` environment variables.
+    pub fn from_env() -> Self {
+        Overrides {
+            config: std::env::var_os("TG_CONFIG").map(PathBuf::from),
+            db: std::env::var_os("TG_DB").map(PathBuf::from),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.config.is_none() && self.db.is_none()
+    }
+}
+
 impl Config {
-    pub fn load() -> Result<Self> {
+    /// Loads the config of `profile`, or the default one if `None`,
+    /// honouring the `TG_CONFIG` and `TG_DB` environment variables.
+    ///
+    /// Each profile has its own config in `CONFIG_DIR/profiles/NAME.toml`
+    /// and keeps its database in `DATA_DIR/profiles/NAME`.
+    pub fn load(profile: Option<&str>) -> Result<Self> {
+        Config::load_with(profile, &Overrides::from_env())
+    }
+
+    /// Loads the config of `profile` like [`Config::load`], replacing the paths set in `overrides`.
+    ///
+    /// An overridden database keeps the inbox, PID and log files in its own directory.
+    /// Fails if `profile` was never created, see [`Config::create_with`].
+    pub fn load_with(profile: Option<&str>, overrides: &Overrides) -> Result<Self> {
+        Config::open(profile, overrides, false)
+    }
+
+    /// Loads the config of `profile` like [`Config::load_with`], creating the profile if needed.
+    pub fn create_with(profile: Option<&str>, overrides: &Overrides) -> Result<Self> {
+        Config::open(profile, overrides, true)
+    }
+
+    fn open(profile: Option<&str>, overrides: &Overrides, create: bool) -> Result<Self> {
         let dirs = get_dirs();
-        let config_path = dirs.config_dir().join("config.toml");
-        let data_path = dirs.data_dir().join("db.sqlite");
+        let (mut config_path, mut data_dir) = match profile {
+            Some(name) => {
+                validate_profile(name)?;
+                (
+                    dirs.config_dir()
+                        .join("profiles")
+                        .join(format!("{name}.toml")),
+                    dirs.data_dir().join("profiles").join(name),
+                )
+            }
+            None => (
+                dirs.config_dir().join("config.toml"),
+                dirs.data_dir().to_path_buf(),
+            ),
+        };
+        let mut db_path = data_dir.join("db.sqlite");
+        if let Some(config) = &overrides.config {
+            config_path = std::path::absolute(config)?;
+        } else if let Some(name) = profile.filter(|_| !create) {
+            if !config_path.try_exists()? {
+                bail!(
+                    "the profile {name:?} does not exist, create it with \
+                    'tg --profile {name} mount MOUNTPOINT' or 'tg --profile {name} config set KEY VALUE'"
+                );
+            }
+        }
+        if let Some(db) = &overrides.db {
+            db_path = std::path::absolute(db)?;
+            data_dir = db_path
+                .parent()
+                .with_context(|| format!("{db_path:?} is not a file"))?
+                .to_path_buf();
+        }
+        std::fs::create_dir_all(&data_dir)?;
 
         let config = confy::load_path(&config_path)?;
         Ok(Config {
+            profile: profile.map(String::from),
             config_path,
-            db_path: data_path,
+            db_path,
+            data_dir,
             ..config
         })
     }
 
+    /// Loads the default config followed by every profile.
+    pub fn load_all() -> Result<Vec<Self>> {
+        let mut configs = vec![Config::load(None)?];
+        for profile in profiles()? {
+            configs.push(Config::load(Some(&profile))?);
+        }
+        Ok(configs)
+    }
+
+    /// Name of the loaded profile, `None` if it's the default one.
+    pub fn profile(&self) -> Option<&str> {
+        self.profile.as_deref()
+    }
+
     pub fn store(&self) -> ConfyResult {
         confy::store_path(self.config_path(), self)
     }
@@ -59,20 +295,69 @@ impl Config {
         &self.tag_prefix
     }
 
-    pub fn set_tag_prefix(&mut self, tag_prefix: String) -> ConfyResult {
+    /// Changes the tag prefix, failing if it's not valid with the current file prefix.
+    ///
+    /// Running mounts switch to the new prefix, see [`Prefixes`].
+    pub fn set_tag_prefix(&mut self, tag_prefix: String) -> Result<()> {
+        Prefixes {
+            tag: tag_prefix.clone(),
+            file: self.file_prefix.clone(),
+        }
+        .validate()?;
         self.tag_prefix = tag_prefix;
-        self.store()
+        Ok(self.store()?)
     }
 
     pub fn file_prefix(&self) -> &str {
         &self.file_prefix
     }
 
-    pub fn set_file_prefix(&mut self, file_prefix: String) -> ConfyResult {
+    /// Changes the file prefix, failing if it's not valid with the current tag prefix.
+    ///
+    /// Running mounts switch to the new prefix, see [`Prefixes`].
+    pub fn set_file_prefix(&mut self, file_prefix: String) -> Result<()> {
+        Prefixes {
+            tag: self.tag_prefix.clone(),
+            file: file_prefix.clone(),
+        }
+        .validate()?;
         self.file_prefix = file_prefix;
+        Ok(self.store()?)
+    }
+
+    pub fn prefixes(&self) -> Prefixes {
+        Prefixes {
+            tag: self.tag_prefix.clone(),
+            file: self.file_prefix.clone(),
+        }
+    }
+
+    /// Directory where files created inside the mount are stored, defaults to `inbox` in the data directory of the profile.
+    pub fn inbox(&self) -> PathBuf {
+        self.inbox
+            .clone()
+            .unwrap_or_else(|| self.data_dir.join("inbox"))
+    }
+
+    pub fn set_inbox(&mut self, inbox: PathBuf) -> ConfyResult {
+        self.inbox = Some(inbox);
         self.store()
     }
 
+    /// Number of automatic backups kept, 0 if disabled.
+    pub fn backups(&self) -> usize {
+        self.backups
+    }
+
+    pub fn mount_options(&self) -> &MountOptions {
+        &self.mount
+    }
+
+    /// Mount options that will be used by the current process, changes are not stored.
+    pub fn mount_options_mut(&mut self) -> &mut MountOptions {
+        &mut self.mount
+    }
+
     pub fn config_path(&self) -> &Path {
         &self.config_path
     }
@@ -80,6 +365,177 @@ impl Config {
     pub fn db_path(&self) -> &Path {
         &self.db_path
     }
+
+    /// PID file locked by the running mount.
+    pub fn pid_path(&self) -> PathBuf {
+        self.data_dir.join("tg.pid")
+    }
+
+    /// Log file of mounts started with `--daemon`.
+    pub fn log_path(&self) -> PathBuf {
+        self.data_dir.join("tg.log")
+    }
+
+    /// The stored config in TOML.
+    pub fn to_toml(&self) -> Result<String> {
+        Ok(toml::to_string(self)?)
+    }
+
+    /// Stored value of `key`, `None` if unset.
+    pub fn get(&self, key: &str) -> Result<Option<String>> {
+        kind(key)?;
+        let table = toml::Value::try_from(self)?;
+        let value = key.split('.').try_fold(&table, |v, k| v.get(k));
+        Ok(value.map(|v| match v {
+            toml::Value::String(s) => s.clone(),
+            v => v.to_string(),
+        }))
+    }
+
+    /// Parses `value` as the type of `key` and stores it.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        let value = match kind(key)? {
+            Kind::Bool => toml::Value::Boolean(
+                value
+                    .parse()
+                    .with_context(|| format!("{key} must be true or false, got {value:?}"))?,
+            ),
+            Kind::Integer => {
+                let n = value
+                    .parse::<u64>()
+                    .ok()
+                    .and_then(|n| i64::try_from(n).ok())
+                    .with_context(|| {
+                        format!("{key} must be a non-negative integer, got {value:?}")
+                    })?;
+                toml::Value::Integer(n)
+            }
+            Kind::String => toml::Value::String(value.into()),
+            Kind::Path => {
+                if value.is_empty() {
+                    bail!("{key} cannot be empty, use 'tg config reset {key}' to unset it");
+                }
+                let path = std::path::absolute(value)?;
+                toml::Value::String(path.to_string_lossy().into_owned())
+            }
+        };
+        self.update(key, Some(value))
+    }
+
+    /// Restores the default value of `key`, or of every key if `None`.
+    pub fn reset(&mut self, key: Option<&str>) -> Result<()> {
+        match key {
+            Some(key) => {
+                kind(key)?;
+                let default = toml::Value::try_from(Config::default())?;
+                let value = key.split('.').try_fold(&default, |v, k| v.get(k));
+                self.update(key, value.cloned())
+            }
+            None => self.replace(Config::default()),
+        }
+    }
+
+    /// Sets or removes `key` in the serialized config, validating the result before storing it.
+    fn update(&mut self, key: &str, value: Option<toml::Value>) -> Result<()> {
+        let mut table = toml::Value::try_from(&*self)?;
+        let (parents, name) = key.rsplit_once('.').map_or(("", key), |(p, n)| (p, n));
+        let mut parent = &mut table;
+        for k in parents.split('.').filter(|k| !k.is_empty()) {
+            parent = parent
+                .as_table_mut()
+                .and_then(|t| t.get_mut(k))
+                .with_context(|| format!("{k} is not a table"))?;
+        }
+        let parent = parent.as_table_mut().context("config is not a table")?;
+        match value {
+            Some(value) => parent.insert(name.into(), value),
+            None => parent.remove(name),
+        };
+        self.replace(table.try_into()?)
+    }
+
+    /// Stores `config` in place of the current one, keeping its profile and paths.
+    fn replace(&mut self, config: Config) -> Result<()> {
+        config.prefixes().validate()?;
+        config.mount.validate()?;
+        *self = Config {
+            profile: self.profile.take(),
+            config_path: std::mem::take(&mut self.config_path),
+            data_dir: std::mem::take(&mut self.data_dir),
+            db_path: std::mem::take(&mut self.db_path),
+            ..config
+        };
+        Ok(self.store()?)
+    }
+}
+
+/// Type of a config value, used to parse the values given to [`Config::set`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Bool,
+    Integer,
+    String,
+    Path,
+}
+
+/// Every config key, mount options are nested under `mount.`.
+pub const KEYS: &[(&str, Kind)] = &[
+    ("mountpoint", Kind::Path),
+    ("tag_prefix", Kind::String),
+    ("file_prefix", Kind::String),
+    ("inbox", Kind::Path),
+    ("backups", Kind::Integer),
+    ("mount.passthrough", Kind::Bool),
+    ("mount.read_only", Kind::Bool),
+    ("mount.cache_size", Kind::Integer),
+    ("mount.cache_ttl", Kind::Integer),
+    ("mount.allow_other", Kind::Bool),
+    ("mount.default_permissions", Kind::Bool),
+    ("mount.auto_unmount", Kind::Bool),
+    ("mount.fsname", Kind::String),
+    ("mount.subtype", Kind::String),
+    ("mount.attr_ttl", Kind::Integer),
+    ("mount.entry_ttl", Kind::Integer),
+    ("mount.watch", Kind::Bool),
+];
+
+fn kind(key: &str) -> Result<Kind> {
+    match KEYS.iter().find(|(k, _)| *k == key) {
+        Some((_, kind)) => Ok(*kind),
+        None => bail!(
+            "unknown config key {key:?}, expected one of: {}",
+            KEYS.iter().map(|(k, _)| *k).collect::<Vec<_>>().join(", ")
+        ),
+    }
+}
+
+/// Checks that `name` can be used as a file name.
+fn validate_profile(name: &str) -> Result<()> {
+    if name.is_empty() || name.starts_with('.') || name.contains('/') {
+        bail!("invalid profile name {name:?}, it cannot be empty, start with '.' or contain '/'");
+    }
+    Ok(())
+}
+
+/// Names of the created profiles, sorted alphabetically.
+pub fn profiles() -> Result<Vec<String>> {
+    let dir = get_dirs().config_dir().join("profiles");
+    let entries = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut profiles = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|e| e == "toml") {
+            if let Some(name) = path.file_stem() {
+                profiles.push(name.to_string_lossy().into_owned());
+            }
+        }
+    }
+    profiles.sort();
+    Ok(profiles)
 }
 
 fn get_dirs() -> directories::ProjectDirs {
@@ -88,3 +544,51 @@ fn get_dirs() -> directories::ProjectDirs {
         "could not get app directories"
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validate(tag: &str, file: &str) -> Result<()> {
+        Prefixes {
+            tag: tag.to_string(),
+            file: file.to_string(),
+        }
+        .validate()
+    }
+
+    #[test]
+    fn valid_prefixes() {
+        Config::default().prefixes().validate().unwrap();
+        validate("#", "@").unwrap();
+        validate("tag:", "file:").unwrap();
+    }
+
+    #[test]
+    fn empty_prefixes() {
+        assert!(validate("", "_").is_err());
+        assert!(validate(":", "").is_err());
+        assert!(validate("", "").is_err());
+    }
+
+    #[test]
+    fn equal_prefixes() {
+        assert!(validate(":", ":").is_err());
+        assert!(validate("ab", "ab").is_err());
+    }
+
+    #[test]
+    fn prefixes_starting_with_each_other() {
+        assert!(validate(":", "::").is_err());
+        assert!(validate("_f", "_").is_err());
+        // Sharing a start is fine as long as neither is a prefix of the other
+        validate("_t", "_f").unwrap();
+    }
+
+    #[test]
+    fn prefixes_with_separators() {
+        assert!(validate("/", "_").is_err());
+        assert!(validate(":", "a/b").is_err());
+        assert!(validate(":\0", "_").is_err());
+    }
+}
@@ -0,0 +1,215 @@
+use anyhow::{bail, Context, Result};
+use log::debug;
+use std::{
+    fs::File,
+    io::{Read, Seek, Write},
+    os::{fd::AsRawFd, unix::process::CommandExt},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::{Duration, Instant},
+};
+
+/// How long to wait for a background mount to start or stop.
+const TIMEOUT: Duration = Duration::from_secs(10);
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A running mount, as recorded in its PID file.
+#[derive(Debug, Clone)]
+pub struct Status {
+    pub pid: u32,
+    pub mountpoint: PathBuf,
+    pub db_path: PathBuf,
+}
+
+/// PID file locked for as long as the mount is running, removed when dropped.
+///
+/// The lock is what tells a live mount apart from a stale file left by a crash.
+pub struct PidFile {
+    path: PathBuf,
+    _file: File,
+}
+
+fn flock(file: &File, operation: libc::c_int) -> std::io::Result<bool> {
+    if unsafe { libc::flock(file.as_raw_fd(), operation | libc::LOCK_NB) } == 0 {
+        return Ok(true);
+    }
+    let e = std::io::Error::last_os_error();
+    match e.raw_os_error() {
+        Some(libc::EWOULDBLOCK) => Ok(false),
+        _ => Err(e),
+    }
+}
+
+impl PidFile {
+    /// Creates and locks the PID file at `path`, failing if another mount holds it.
+    pub fn create(path: impl Into<PathBuf>, status: &Status) -> Result<Self> {
+        let path = path.into();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .with_context(|| format!("could not open {path:?}"))?;
+        if !flock(&file, libc::LOCK_EX)? {
+            let mut running = String::new();
+            file.read_to_string(&mut running)?;
+            match parse(&running) {
+                Some(s) => bail!("already mounted to {:?} by process {}", s.mountpoint, s.pid),
+                None => bail!("already mounted, {path:?} is locked by another process"),
+            }
+        }
+        file.set_len(0)?;
+        file.rewind()?;
+        write!(
+            file,
+            "{}\n{}\n{}\n",
+            status.pid,
+            status.mountpoint.display(),
+            status.db_path.display()
+        )?;
+        file.flush()?;
+
+        Ok(PidFile { path, _file: file })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            debug!("could not remove {:?}: {e}", self.path);
+        }
+    }
+}
+
+fn parse(contents: &str) -> Option<Status> {
+    let mut lines = contents.lines();
+    Some(Status {
+        pid: lines.next()?.parse().ok()?,
+        mountpoint: lines.next()?.into(),
+        db_path: lines.next()?.into(),
+    })
+}
+
+/// Returns the mount recorded in the PID file at `path`, or `None` if nothing is mounted.
+pub fn status(path: impl AsRef<Path>) -> Result<Option<Status>> {
+    let path = path.as_ref();
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("could not open {path:?}")),
+    };
+    // Locking succeeds only if no process holds the file
+    if flock(&file, libc::LOCK_SH)? {
+        debug!("Ignoring stale PID file {path:?}");
+        return Ok(None);
+    }
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    Ok(parse(&contents))
+}
+
+/// Returns whether `mountpoint` appears in the mount table.
+pub fn is_mounted(mountpoint: impl AsRef<Path>) -> Result<bool> {
+    let mountpoint = mountpoint.as_ref().to_string_lossy();
+    let mounts = std::fs::read_to_string("/proc/self/mounts")?;
+    Ok(mounts
+        .lines()
+        .filter_map(|l| l.split(' ').nth(1))
+        .any(|m| unescape(m) == mountpoint))
+}
+
+/// Undoes the octal escaping of spaces, tabs, newlines and backslashes in `/proc/self/mounts`.
+fn unescape(field: &str) -> String {
+    field
+        .replace("\\040", " ")
+        .replace("\\011", "\t")
+        .replace("\\012", "\n")
+        .replace("\\134", "\\")
+}
+
+/// Runs the current command again detached from the terminal, without `--daemon`.
+///
+/// Output is appended to `log_path`, returns once the child has written its PID file.
+pub fn spawn(pid_path: impl AsRef<Path>, log_path: impl AsRef<Path>) -> Result<Status> {
+    let (pid_path, log_path) = (pid_path.as_ref(), log_path.as_ref());
+    if let Some(dir) = log_path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let log = File::options()
+        .create(true)
+        .append(true)
+        .open(log_path)
+        .with_context(|| format!("could not open {log_path:?}"))?;
+
+    let mut child = Command::new(std::env::current_exe()?);
+    child
+        .args(std::env::args_os().skip(1).filter(|a| a != "--daemon"))
+        .stdin(Stdio::null())
+        .stdout(log.try_clone()?)
+        .stderr(log);
+    // SAFETY: setsid is async-signal-safe
+    unsafe {
+        child.pre_exec(|| {
+            if libc::setsid() < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let mut child = child
+        .spawn()
+        .context("could not start the background process")?;
+
+    let start = Instant::now();
+    while start.elapsed() < TIMEOUT {
+        if let Some(code) = child.try_wait()? {
+            bail!("the background process exited with {code}, see {log_path:?}");
+        }
+        if let Some(status) = status(pid_path)? {
+            if status.pid == child.id() && is_mounted(&status.mountpoint)? {
+                return Ok(status);
+            }
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+    bail!(
+        "the background process {} did not mount in {TIMEOUT:?}, see {log_path:?}",
+        child.id()
+    )
+}
+
+/// Unmounts `mountpoint` with `fusermount`, letting the mounting process exit cleanly.
+pub fn unmount(mountpoint: impl AsRef<Path>) -> Result<()> {
+    let mountpoint = mountpoint.as_ref();
+    for program in ["fusermount3", "fusermount"] {
+        let output = match Command::new(program).arg("-u").arg(mountpoint).output() {
+            Ok(output) => output,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e).with_context(|| format!("could not run {program}")),
+        };
+        if !output.status.success() {
+            bail!(
+                "could not unmount {mountpoint:?}: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        return Ok(());
+    }
+    bail!("could not unmount {mountpoint:?}, neither fusermount3 nor fusermount were found")
+}
+
+/// Waits until the mount recorded in `pid_path` has exited.
+pub fn wait_exit(pid_path: impl AsRef<Path>) -> Result<()> {
+    let start = Instant::now();
+    while let Some(status) = status(&pid_path)? {
+        if start.elapsed() > TIMEOUT {
+            bail!("process {} is still running after {TIMEOUT:?}", status.pid);
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+    Ok(())
+}
@@ -0,0 +1,225 @@
+use anyhow::{bail, Context, Result};
+use log::debug;
+use std::path::{Path, PathBuf};
+
+use crate::sql;
+
+/// Checks that `name` can be displayed without being confused with a duplicate.
+pub fn validate_name(name: &str) -> Result<()> {
+    if name.starts_with(sql::DUP_SEP) {
+        bail!(
+            "invalid name {name:?}, files cannot start with '{}'",
+            sql::DUP_SEP
+        );
+    }
+    if let Some(sql::DUP_SEP) = name.chars().find(|n| !n.is_ascii_digit()) {
+        bail!(
+            "invalid name {name:?}, files cannot start with a number and '{}'",
+            sql::DUP_SEP
+        );
+    }
+    Ok(())
+}
+
+/// Adds `tags` to `file`, registering both if they don't exist.
+///
+/// If `name` is provided, it will be displayed instead of the real file name.
+/// The path is stored relative to the deepest root containing it, if any.
+///
+/// Returns the canonicalized path of the file.
+pub fn add(
+    db: &mut rusqlite::Connection,
+    file: impl AsRef<Path>,
+    tags: impl AsRef<[String]>,
+    name: Option<&str>,
+) -> Result<PathBuf> {
+    let roots = crate::roots::Roots::load(db)?;
+    add_with(db, &roots, file, tags, name)
+}
+
+/// Adds `tags` to `file` like [`add`], storing its path with `roots`.
+///
+/// Returns the canonicalized path of the file.
+pub fn add_with(
+    db: &mut rusqlite::Connection,
+    roots: &crate::roots::Roots,
+    file: impl AsRef<Path>,
+    tags: impl AsRef<[String]>,
+    name: Option<&str>,
+) -> Result<PathBuf> {
+    let path = canonicalize(file.as_ref(), name)?;
+    insert(db, &roots.store(&path), tags.as_ref(), name)?;
+    Ok(path)
+}
+
+/// Adds `tags` to `file` like [`add`], storing its path relative to `root`.
+///
+/// Returns the canonicalized path of the file.
+pub fn add_relative(
+    db: &mut rusqlite::Connection,
+    root: impl AsRef<Path>,
+    file: impl AsRef<Path>,
+    tags: impl AsRef<[String]>,
+    name: Option<&str>,
+) -> Result<PathBuf> {
+    let root = root.as_ref();
+    let path = canonicalize(file.as_ref(), name)?;
+    let relative = path
+        .strip_prefix(root)
+        .with_context(|| format!("the file {path:?} is outside of {root:?}"))?;
+    insert(db, &relative.to_string_lossy(), tags.as_ref(), name)?;
+    Ok(path)
+}
+
+/// Validates the names of `file` and returns its canonicalized path.
+fn canonicalize(file: &Path, name: Option<&str>) -> Result<PathBuf> {
+    if let Some(name) = file.file_name() {
+        validate_name(&name.to_string_lossy())?;
+    }
+    if let Some(name) = name {
+        validate_name(name)?;
+    }
+
+    file.canonicalize()
+        .with_context(|| format!("the file {file:?} could not be found"))
+}
+
+/// Registers `path` with `tags` in a single transaction.
+fn insert(
+    db: &mut rusqlite::Connection,
+    path: &str,
+    tags: &[String],
+    name: Option<&str>,
+) -> Result<()> {
+    debug!("Adding {path:?} : {tags:?}");
+
+    let tx = db.transaction()?;
+    {
+        tx.execute(sql::INSERT_FILE, [path])
+            .context("could not insert file")?;
+        if let Some(name) = name {
+            tx.execute(sql::SET_FILE_NAME, [path, name])
+                .context("could not set file name")?;
+        }
+        let mut insert_tag_stmt = tx.prepare_cached(sql::INSERT_TAG)?;
+        let mut insert_filetag_stmt = tx.prepare_cached(sql::INSERT_FILETAG)?;
+        for tag in tags {
+            insert_tag_stmt
+                .execute([tag])
+                .context("could not insert tag")?;
+            insert_filetag_stmt
+                .execute([path, tag.as_str()])
+                .context("could not insert filetag")?;
+        }
+    }
+    tx.commit()?;
+
+    Ok(())
+}
+
+/// Returns the tags of the registered `path`, as stored in the database, sorted alphabetically.
+pub fn file_tags(db: &rusqlite::Connection, path: &str) -> rusqlite::Result<Vec<String>> {
+    let mut stmt = db.prepare_cached(sql::GET_FILE_TAGS)?;
+    let tags = stmt.query_map([path], |r| r.get(0))?;
+    tags.collect()
+}
+
+/// Returns the stored paths of the files tagged with every tag in `tags`, or all if empty.
+pub fn files(db: &rusqlite::Connection, tags: &[String]) -> rusqlite::Result<Vec<String>> {
+    if tags.is_empty() {
+        let mut stmt = db.prepare_cached(sql::ALL_FILES)?;
+        let paths = stmt.query_map([], |r| r.get(0))?;
+        return paths.collect();
+    }
+    // Repeated tags would never match the count of distinct tags of a file
+    let mut tags = tags.to_vec();
+    tags.sort_unstable();
+    tags.dedup();
+
+    let mut stmt = db.prepare(&sql::matching_tags_files(tags.len()))?;
+    let mut paths = stmt
+        .query_map(rusqlite::params_from_iter(&tags), |r| {
+            r.get::<_, Option<String>>("path")
+        })?
+        .filter_map(Result::transpose)
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    paths.sort();
+    Ok(paths)
+}
+
+/// Replaces the tags of the registered `path` with `tags`.
+pub fn set_tags(db: &mut rusqlite::Connection, path: &str, tags: &[&str]) -> Result<()> {
+    let current = file_tags(db, path)?;
+    let remove = current
+        .iter()
+        .map(String::as_str)
+        .filter(|t| !tags.contains(t))
+        .collect::<Vec<_>>();
+    let add = tags
+        .iter()
+        .filter(|t| !current.iter().any(|c| c == *t))
+        .copied()
+        .collect::<Vec<_>>();
+    retag(db, path, &remove, &add, None)
+}
+
+/// Removes `remove` and adds `add` to the already registered `path` in a single transaction.
+///
+/// If `name` is provided, it will be displayed instead of the real file name.
+pub fn retag(
+    db: &mut rusqlite::Connection,
+    path: &str,
+    remove: &[&str],
+    add: &[&str],
+    name: Option<&str>,
+) -> Result<()> {
+    if let Some(name) = name {
+        validate_name(name)?;
+    }
+
+    debug!("Retagging {path:?} : -{remove:?} +{add:?}");
+
+    let tx = db.transaction()?;
+    {
+        let mut delete_filetag_stmt = tx.prepare_cached(sql::DELETE_FILETAG)?;
+        for tag in remove {
+            delete_filetag_stmt
+                .execute([path, tag])
+                .context("could not delete filetag")?;
+        }
+        let mut insert_tag_stmt = tx.prepare_cached(sql::INSERT_TAG)?;
+        let mut insert_filetag_stmt = tx.prepare_cached(sql::INSERT_FILETAG)?;
+        for tag in add {
+            insert_tag_stmt
+                .execute([tag])
+                .context("could not insert tag")?;
+            insert_filetag_stmt
+                .execute([path, tag])
+                .context("could not insert filetag")?;
+        }
+        if let Some(name) = name {
+            tx.execute(sql::SET_FILE_NAME, [path, name])
+                .context("could not set file name")?;
+        }
+    }
+    tx.commit()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_with_repeated_tags() {
+        let mut db = rusqlite::Connection::open_in_memory().unwrap();
+        sql::migrate(&mut db).unwrap();
+        insert(&mut db, "/a", &["x".into(), "y".into()], None).unwrap();
+        insert(&mut db, "/b", &["x".into()], None).unwrap();
+
+        let tags = |tags: &[&str]| tags.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+        assert_eq!(files(&db, &tags(&["x", "x"])).unwrap(), ["/a", "/b"]);
+        assert_eq!(files(&db, &tags(&["y", "x", "y"])).unwrap(), ["/a"]);
+    }
+}
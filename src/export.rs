@@ -0,0 +1,262 @@
+use anyhow::{bail, Result};
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use crate::{roots::Roots, sql};
+
+/// File format of `tg export` and `tg import`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// `{"tags": [Tag...], "files": [File...]}`, with one file per line
+    Json,
+    /// `path,tag` rows with a header, files without tags have an empty tag
+    Csv,
+}
+
+impl std::str::FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            _ => bail!("unknown format {s:?}, expected json or csv"),
+        }
+    }
+}
+
+impl Format {
+    /// Whether the format carries the display names of the files.
+    pub fn has_names(self) -> bool {
+        self == Format::Json
+    }
+}
+
+impl std::fmt::Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Format::Json => write!(f, "json"),
+            Format::Csv => write!(f, "csv"),
+        }
+    }
+}
+
+/// A tag and its metadata, as exported.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Tag {
+    pub tag: String,
+    /// Unix time of the last change to the files of the tag
+    pub modified: i64,
+    pub files: u64,
+}
+
+/// A file and its tags, as exported.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct File {
+    /// Absolute path of the file
+    pub path: PathBuf,
+    /// Name displayed in the mount
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub tags: Vec<String>,
+}
+
+/// Files included in an export.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    /// Only files tagged with all these tags
+    pub tags: Vec<String>,
+    /// Only files inside this directory
+    pub under: Option<PathBuf>,
+}
+
+/// Number of files written by [`export`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Exported {
+    pub files: u64,
+    /// Files left out because their root was removed
+    pub skipped: u64,
+}
+
+/// Calls `f` with every file matching `filter`, reading them one at a time.
+///
+/// Returns the number of files skipped because their root was removed.
+pub fn for_each_file(
+    db: &rusqlite::Connection,
+    roots: &Roots,
+    filter: &Filter,
+    mut f: impl FnMut(File) -> Result<()>,
+) -> Result<u64> {
+    let mut tags = filter.tags.clone();
+    tags.sort_unstable();
+    tags.dedup();
+
+    let mut stmt = db.prepare(&sql::export_files(tags.len()))?;
+    let mut rows = stmt.query(rusqlite::params_from_iter(&tags))?;
+
+    let mut current: Option<(i64, File)> = None;
+    let mut skipped = None;
+    let mut skipped_count = 0;
+    let mut emit = |file: Option<(i64, File)>| -> Result<()> {
+        let Some((_, mut file)) = file else {
+            return Ok(());
+        };
+        if filter
+            .under
+            .as_ref()
+            .is_some_and(|d| !file.path.starts_with(d))
+        {
+            return Ok(());
+        }
+        file.tags.sort_unstable();
+        f(file)
+    };
+    while let Some(row) = rows.next()? {
+        let id: i64 = row.get(0)?;
+        let tag: Option<String> = row.get(3)?;
+        if skipped == Some(id) {
+            continue;
+        }
+        match &mut current {
+            Some((current_id, file)) if *current_id == id => file.tags.extend(tag),
+            _ => {
+                let stored: String = row.get(1)?;
+                let Some(path) = roots.resolve(&stored) else {
+                    log::warn!("skipping {stored:?}, its root was removed");
+                    skipped = Some(id);
+                    skipped_count += 1;
+                    continue;
+                };
+                let name: Option<String> = row.get(2)?;
+                // Only keep the name if it's an alias
+                let name = name.filter(|n| path.file_name() != Some(n.as_ref()));
+                let file = File {
+                    path,
+                    name,
+                    tags: tag.into_iter().collect(),
+                };
+                emit(current.replace((id, file)))?;
+            }
+        }
+    }
+    emit(current)?;
+    Ok(skipped_count)
+}
+
+/// Every tag with its metadata, sorted alphabetically.
+pub fn tags(db: &rusqlite::Connection) -> rusqlite::Result<Vec<Tag>> {
+    let mut stmt = db.prepare(sql::EXPORT_TAGS)?;
+    let tags = stmt.query_map([], |r| {
+        Ok(Tag {
+            tag: r.get(0)?,
+            modified: r.get(1)?,
+            files: r.get(2)?,
+        })
+    })?;
+    tags.collect()
+}
+
+/// Quotes `field` if it contains a separator, quote or line break.
+pub fn csv_field(field: &str) -> std::borrow::Cow<'_, str> {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\"")).into()
+    } else {
+        field.into()
+    }
+}
+
+/// Writes the files matching `filter` to `out` as `format`, without loading them all in memory.
+///
+/// Returns the number of exported and skipped files.
+pub fn export(
+    db: &rusqlite::Connection,
+    roots: &Roots,
+    filter: &Filter,
+    format: Format,
+    out: &mut impl Write,
+) -> Result<Exported> {
+    let mut count = 0;
+    let skipped = match format {
+        Format::Json => {
+            write!(out, "{{\"tags\":")?;
+            serde_json::to_writer(&mut *out, &tags(db)?)?;
+            write!(out, ",\n\"files\":[")?;
+            let skipped = for_each_file(db, roots, filter, |file| {
+                if count > 0 {
+                    write!(out, ",")?;
+                }
+                writeln!(out)?;
+                serde_json::to_writer(&mut *out, &file)?;
+                count += 1;
+                Ok(())
+            })?;
+            writeln!(out, "\n]}}")?;
+            skipped
+        }
+        Format::Csv => {
+            writeln!(out, "path,tag")?;
+            for_each_file(db, roots, filter, |file| {
+                let path = file.path.to_string_lossy();
+                if file.tags.is_empty() {
+                    writeln!(out, "{},", csv_field(&path))?;
+                }
+                for tag in &file.tags {
+                    writeln!(out, "{},{}", csv_field(&path), csv_field(tag))?;
+                }
+                count += 1;
+                Ok(())
+            })?
+        }
+    };
+    out.flush()?;
+    Ok(Exported {
+        files: count,
+        skipped,
+    })
+}
+
+/// Opens `path` for writing, or stdout if `None`.
+pub fn output(path: Option<&Path>) -> Result<Box<dyn Write>> {
+    Ok(match path {
+        Some(path) => Box::new(std::io::BufWriter::new(std::fs::File::create(path)?)),
+        None => Box::new(std::io::BufWriter::new(std::io::stdout().lock())),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_of_removed_roots_are_skipped() {
+        let mut db = rusqlite::Connection::open_in_memory().unwrap();
+        sql::migrate(&mut db).unwrap();
+        for (path, tag) in [("/a", "x"), ("~9/b", "x"), ("~9/c", "y")] {
+            db.execute(sql::INSERT_FILE, [path]).unwrap();
+            db.execute(sql::INSERT_TAG, [tag]).unwrap();
+            db.execute(sql::INSERT_FILETAG, [path, tag]).unwrap();
+        }
+        let roots = Roots::load(&db).unwrap();
+
+        let mut out = Vec::new();
+        let exported = export(&db, &roots, &Filter::default(), Format::Csv, &mut out).unwrap();
+        assert_eq!(
+            exported,
+            Exported {
+                files: 1,
+                skipped: 2
+            }
+        );
+        assert_eq!(String::from_utf8(out).unwrap(), "path,tag\n/a,x\n");
+
+        let filter = Filter {
+            tags: vec!["x".into(), "x".into()],
+            under: None,
+        };
+        let mut out = Vec::new();
+        let exported = export(&db, &roots, &filter, Format::Csv, &mut out).unwrap();
+        assert_eq!(exported.files, 1);
+    }
+}
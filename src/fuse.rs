@@ -1,57 +1,90 @@
 use std::{
+    collections::HashMap,
     ffi::{OsStr, OsString},
+    fs::File,
     hash::{Hash, Hasher},
-    os::unix::ffi::OsStrExt,
-    time::UNIX_EPOCH,
+    os::unix::{
+        ffi::{OsStrExt, OsStringExt},
+        fs::{FileExt, MetadataExt, OpenOptionsExt, PermissionsExt},
+    },
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use anyhow::{Context, Result};
 use fuse_mt as fusemt;
-use log::{debug, info};
-
-use crate::{config::Config, ok_or_panic, sql};
-
-const TTL: std::time::Duration = std::time::Duration::from_secs(1); // 1 second
-
-const ROOT_DIR_ATTR: fusemt::FileAttr = fusemt::FileAttr {
-    size: 0,
-    blocks: 0,
-    atime: UNIX_EPOCH, // 1970-01-01 00:00:00
-    mtime: UNIX_EPOCH,
-    ctime: UNIX_EPOCH,
-    kind: fusemt::FileType::Directory,
-    perm: 0o755,
-    nlink: 2,
-    uid: 1000,
-    gid: 100,
-    rdev: 0,
-
-    // macOS only
-    crtime: UNIX_EPOCH,
-    flags: 0,
-};
+use log::{debug, error, info};
 
-const LINK_ATTR: fusemt::FileAttr = fusemt::FileAttr {
-    size: 0,
-    blocks: 0,
-    atime: UNIX_EPOCH, // 1970-01-01 00:00:00
-    mtime: UNIX_EPOCH,
-    ctime: UNIX_EPOCH,
-    kind: fusemt::FileType::Symlink,
-    perm: 0o755,
-    nlink: 2,
-    uid: 1000,
-    gid: 100,
-    rdev: 0,
-
-    // macOS only
-    crtime: UNIX_EPOCH,
-    flags: 0,
+use crate::{
+    cache::Cache,
+    config::{Config, Prefixes},
+    ok_or_panic,
+    pool::{Pool, ReadConnection, WriteConnection},
+    roots::Roots,
+    sql,
 };
 
+/// How often the database is checked for changes made by other processes.
+const WATCH_INTERVAL: std::time::Duration = std::time::Duration::from_millis(250);
+
+// The extended attributes are only reachable in passthrough mode, Linux refuses `user.*`
+// attributes on symlinks and tools like `getfattr` follow them to the real file instead.
+
+/// Extended attribute with the comma separated tags of a file, can be modified.
+const XATTR_TAGS: &str = "user.tg.tags";
+/// Read-only extended attribute with the real path of a file.
+const XATTR_PATH: &str = "user.tg.path";
+/// Read-only extended attribute with the database id of a file.
+const XATTR_ID: &str = "user.tg.id";
+
 pub struct Fuse {
     db_path: std::path::PathBuf,
+    pool: Pool,
     config: Config,
+    /// Prefixes of the config, updated when it's modified while mounted
+    prefixes: Arc<RwLock<Arc<Prefixes>>>,
+    /// Time the kernel caches attributes and new entries
+    attr_ttl: Duration,
+    entry_ttl: Duration,
+    /// Owner of the mount, used for the tag directories
+    uid: u32,
+    gid: u32,
+    /// Real files opened through the mount, indexed by file handle
+    files: Mutex<HashMap<u64, Arc<File>>>,
+    next_fh: AtomicU64,
+    caches: Arc<Caches>,
+    /// Stops the database watcher when the filesystem is destroyed
+    stop_watcher: Arc<AtomicBool>,
+    /// Whether the database watcher is running, see [`Fuse::ttl`]
+    watching: AtomicBool,
+}
+
+struct Caches {
+    /// Entries of each directory, indexed by its sorted tags
+    listings: Cache<Vec<String>, Arc<Vec<Entry>>>,
+    /// Results of the database checks of `name_exists`, indexed by path
+    exists: Cache<std::path::PathBuf, bool>,
+    /// Roots used to resolve the stored paths
+    roots: Cache<(), Arc<Roots>>,
+}
+
+impl Caches {
+    fn clear(&self) {
+        self.listings.clear();
+        self.exists.clear();
+        self.roots.clear();
+    }
+}
+
+/// Entry of a directory, as returned by `sql::matching_tags_files`.
+///
+/// The path of a file is stored as in the database, see [`Fuse::real_path`].
+#[derive(Debug, Clone)]
+enum Entry {
+    Tag(String),
+    File { name: String, id: i64, path: String },
 }
 
 #[derive(Debug)]
@@ -64,37 +97,126 @@ enum Name<'a> {
 
 impl Fuse {
     pub fn new(db_path: impl Into<std::path::PathBuf>, config: Config) -> Self {
+        let options = config.mount_options();
+        let cache_ttl = Duration::from_secs(options.cache_ttl);
+        let caches = Caches {
+            listings: Cache::new(options.cache_size, cache_ttl),
+            exists: Cache::new(options.cache_size, cache_ttl),
+            roots: Cache::new(options.cache_size.min(1), cache_ttl),
+        };
+        let attr_ttl = Duration::from_secs(options.attr_ttl);
+        let entry_ttl = Duration::from_secs(options.entry_ttl);
+        let db_path = db_path.into();
         Fuse {
-            db_path: db_path.into(),
+            attr_ttl,
+            entry_ttl,
+            pool: Pool::new(&db_path),
+            db_path,
+            prefixes: Arc::new(RwLock::new(Arc::new(config.prefixes()))),
             config,
+            uid: unsafe { libc::getuid() },
+            gid: unsafe { libc::getgid() },
+            files: Mutex::default(),
+            next_fh: AtomicU64::new(1),
+            caches: Arc::new(caches),
+            stop_watcher: Arc::default(),
+            watching: AtomicBool::new(false),
         }
     }
 
-    fn connect_db(&self) -> rusqlite::Result<rusqlite::Connection> {
-        use rusqlite::OpenFlags;
-        rusqlite::Connection::open_with_flags(
-            &self.db_path,
-            OpenFlags::SQLITE_OPEN_NO_MUTEX | OpenFlags::SQLITE_OPEN_READ_ONLY,
-        )
+    /// Time the kernel may cache a reply instead of `ttl`.
+    ///
+    /// The kernel can't be told to forget its entries, so with the `watch` mount option they're
+    /// kept for at most `WATCH_INTERVAL` while the database is watched, to see the changes of
+    /// other processes.
+    fn ttl(&self, ttl: Duration) -> Duration {
+        match self.config.mount_options().watch && self.watching.load(Ordering::Relaxed) {
+            true => ttl.min(WATCH_INTERVAL),
+            false => ttl,
+        }
     }
 
-    fn connect_db_mut(&self) -> rusqlite::Result<rusqlite::Connection> {
-        use rusqlite::OpenFlags;
-        rusqlite::Connection::open_with_flags(
+    fn prefixes(&self) -> Arc<Prefixes> {
+        Arc::clone(&self.prefixes.read().unwrap())
+    }
+
+    /// Clears the caches after modifying the database.
+    fn invalidate(&self) {
+        self.caches.clear();
+    }
+
+    /// Clears the caches whenever another connection modifies the database,
+    /// and switches to the new prefixes when they're changed in the config.
+    ///
+    /// Entries already cached by the kernel are kept until their TTL expires, see [`Fuse::ttl`].
+    fn spawn_watcher(&self) -> rusqlite::Result<()> {
+        let db = rusqlite::Connection::open_with_flags(
             &self.db_path,
-            OpenFlags::SQLITE_OPEN_NO_MUTEX | OpenFlags::SQLITE_OPEN_READ_WRITE,
-        )
+            rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY,
+        )?;
+        let data_version = |db: &rusqlite::Connection| {
+            db.pragma_query_value(None, "data_version", |r| r.get::<_, i64>(0))
+        };
+        let mut version = data_version(&db)?;
+
+        let config_path = self.config.config_path().to_path_buf();
+        let modified =
+            |path: &std::path::Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        let mut config_modified = modified(&config_path);
+
+        let caches = Arc::clone(&self.caches);
+        let prefixes = Arc::clone(&self.prefixes);
+        let stop = Arc::clone(&self.stop_watcher);
+        std::thread::spawn(move || {
+            while !stop.load(Ordering::Relaxed) {
+                std::thread::sleep(WATCH_INTERVAL);
+                let m = modified(&config_path);
+                if m != config_modified {
+                    config_modified = m;
+                    match Prefixes::read(&config_path) {
+                        Ok(new) if new != **prefixes.read().unwrap() => {
+                            info!("[watcher] prefixes changed to {new:?}");
+                            *prefixes.write().unwrap() = Arc::new(new);
+                            caches.clear();
+                        }
+                        Ok(_) => {}
+                        Err(e) => error!("[watcher] keeping the current prefixes: {e:#}"),
+                    }
+                }
+                match data_version(&db) {
+                    Ok(v) if v != version => {
+                        debug!("[watcher] database modified, clearing caches");
+                        version = v;
+                        caches.clear();
+                    }
+                    Ok(_) => {}
+                    Err(e) => error!("[watcher] could not check database version: {e}"),
+                }
+            }
+        });
+        Ok(())
+    }
+
+    fn connect_db(&self) -> rusqlite::Result<ReadConnection<'_>> {
+        self.pool.read()
+    }
+
+    fn connect_db_mut(&self) -> rusqlite::Result<WriteConnection<'_>> {
+        self.pool.write()
     }
-    
+
     fn name_exists<'a>(&self, path: &'a std::path::Path) -> rusqlite::Result<Name<'a>> {
-        fn is_valid<'a>(name: &'a OsStr, config: &Config) -> Name<'a> {
+        /// Validated prefixes never start with each other, so the order of the checks doesn't matter.
+        ///
+        /// Names without a prefix are files, as the ones created inside the mount may lack it.
+        fn is_valid<'a>(name: &'a OsStr, prefixes: &Prefixes) -> Name<'a> {
             let bytes = name.as_bytes();
-            if bytes.starts_with(config.file_prefix().as_bytes()) {
-                Name::File(OsStr::from_bytes(&bytes[config.file_prefix().len()..]))
-            } else if bytes.starts_with(config.tag_prefix().as_bytes()) {
-                Name::Tag(OsStr::from_bytes(&bytes[config.tag_prefix().len()..]))
+            if let Some(file) = bytes.strip_prefix(prefixes.file.as_bytes()) {
+                Name::File(OsStr::from_bytes(file))
+            } else if let Some(tag) = bytes.strip_prefix(prefixes.tag.as_bytes()) {
+                Name::Tag(OsStr::from_bytes(tag))
             } else {
-                Name::None
+                Name::File(name)
             }
         }
 
@@ -116,25 +238,403 @@ impl Fuse {
             return Ok(Name::Root);
         }
 
-        match path.file_name().map(|n| is_valid(n, &self.config)) {
-            Some(name) if check_db(&name)? => Ok(name),
-            _ => Ok(Name::None),
+        match path.file_name().map(|n| is_valid(n, &self.prefixes())) {
+            Some(Name::None) | None => Ok(Name::None),
+            Some(name) => {
+                let exists = self
+                    .caches
+                    .exists
+                    .get_or_try_insert_with(path.to_path_buf(), || check_db(&name))?;
+                Ok(if exists { name } else { Name::None })
+            }
         }
     }
 
-    fn get_used_tags(&self) -> Vec<fusemt::DirectoryEntry> {
-        let db = self.connect_db().unwrap();
-        let mut stmt = db.prepare_cached(sql::GET_USED_TAGS).unwrap();
-        stmt.query_map([], |r| {
-            let name = format!("{}{}", self.config.tag_prefix(), r.get_ref(0)?.as_str()?).into();
-            Ok(fusemt::DirectoryEntry {
-                name,
-                kind: fusemt::FileType::Directory,
-            })
+    /// Returns the tags of each component of `path`, without the tag prefix.
+    fn path_tags<'a>(&self, path: &'a std::path::Path) -> impl Iterator<Item = &'a str> {
+        let prefixes = self.prefixes();
+        path.components()
+            .skip(1)
+            .flat_map(|c| std::str::from_utf8(c.as_os_str().as_bytes()))
+            .flat_map(move |s| s.strip_prefix(prefixes.tag.as_str()))
+    }
+
+    /// Roots of the database, relative to the project root if it's a project database.
+    fn roots(&self) -> Result<Arc<Roots>, libc::c_int> {
+        let roots = self.caches.roots.get_or_try_insert_with((), || {
+            let roots = Roots::load_for(&*self.connect_db()?, &self.db_path)?;
+            Ok::<_, rusqlite::Error>(Arc::new(roots))
+        });
+        roots.map_err(|e| {
+            error!("[roots] database connection failed: {e}");
+            libc::EIO
         })
-        .unwrap()
-        .flatten()
-        .collect()
+    }
+
+    /// Current location of a path stored in the database.
+    fn real_path(&self, stored: &str) -> Result<std::path::PathBuf, libc::c_int> {
+        self.roots()?.resolve(stored).ok_or(libc::ENOENT)
+    }
+
+    /// Finds the file displayed at `path`, returning its id and stored path.
+    fn resolve_file(&self, path: &std::path::Path) -> rusqlite::Result<Option<(i64, String)>> {
+        let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+            return Ok(None);
+        };
+        let Some(name) = name.to_str() else {
+            return Ok(None);
+        };
+        let name = name
+            .strip_prefix(self.prefixes().file.as_str())
+            .unwrap_or(name);
+        if self.path_tags(parent).next().is_none() {
+            return Ok(None);
+        }
+
+        let file = self.listing(parent)?.iter().find_map(|e| match e {
+            Entry::File { name: n, id, path } if n == name => Some((*id, path.clone())),
+            _ => None,
+        });
+        Ok(file)
+    }
+
+    /// Returns the entries of the directory at `path`, the used tags if it's the root.
+    fn listing(&self, path: &std::path::Path) -> rusqlite::Result<Arc<Vec<Entry>>> {
+        let mut tags = self.path_tags(path).map(String::from).collect::<Vec<_>>();
+        tags.sort_unstable();
+        tags.dedup();
+
+        self.caches
+            .listings
+            .get_or_try_insert_with(tags.clone(), || {
+                let db = self.connect_db()?;
+                let entries = if tags.is_empty() {
+                    let mut stmt = db.prepare_cached(sql::GET_USED_TAGS)?;
+                    let entries = stmt.query_map([], |r| Ok(Entry::Tag(r.get(0)?)))?;
+                    entries.collect::<rusqlite::Result<_>>()?
+                } else {
+                    let mut stmt = db.prepare_cached(&sql::matching_tags_files(tags.len()))?;
+                    let params = rusqlite::params_from_iter(&tags);
+                    let entries = stmt.query_map(params, |r| match r.get(0)? {
+                        Some(tag) => Ok(Entry::Tag(tag)),
+                        None => Ok(Entry::File {
+                            name: r.get(1)?,
+                            id: r.get(2)?,
+                            path: r.get(3)?,
+                        }),
+                    })?;
+                    entries.collect::<rusqlite::Result<_>>()?
+                };
+                Ok(Arc::new(entries))
+            })
+    }
+
+    /// Checks that files can be tagged inside `parent`.
+    fn check_tag_dir(&self, parent: &std::path::Path) -> Result<(), libc::c_int> {
+        match self.name_exists(parent) {
+            Ok(Name::Tag(_)) => Ok(()),
+            Ok(Name::Root) => Err(libc::EPERM),
+            Ok(Name::File(_)) => Err(libc::ENOTDIR),
+            Ok(Name::None) => Err(libc::ENOENT),
+            Err(e) => {
+                error!("[check_tag_dir] database connection failed: {e}");
+                Err(libc::EIO)
+            }
+        }
+    }
+
+    /// Creates a new file in the inbox and tags it with the tags of `parent`.
+    ///
+    /// If the inbox already contains a file called `name`, a number is appended to the real name.
+    fn create_file(
+        &self,
+        parent: &std::path::Path,
+        name: &OsStr,
+        mode: u32,
+        flags: u32,
+    ) -> Result<(std::path::PathBuf, File), libc::c_int> {
+        self.check_tag_dir(parent)?;
+
+        let inbox = self.config.inbox();
+        std::fs::create_dir_all(&inbox).map_err(errno)?;
+
+        let name = name.to_string_lossy();
+        let name = name
+            .strip_prefix(self.prefixes().file.as_str())
+            .unwrap_or(&name);
+        crate::db::validate_name(name).map_err(|_| libc::EINVAL)?;
+        let requested = std::path::Path::new(name);
+
+        let flags = flags as libc::c_int;
+        let access = flags & libc::O_ACCMODE;
+        let mut path = inbox.join(name);
+        let mut n = 0;
+        let file = loop {
+            let file = std::fs::OpenOptions::new()
+                .read(access == libc::O_RDONLY || access == libc::O_RDWR)
+                .write(true)
+                .create_new(true)
+                .mode(mode & 0o7777)
+                .custom_flags(flags & !(libc::O_ACCMODE | libc::O_CREAT | libc::O_EXCL))
+                .open(&path);
+            match file {
+                Ok(file) => break file,
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    n += 1;
+                    let stem = requested.file_stem().unwrap_or_default().to_string_lossy();
+                    path = match requested.extension() {
+                        Some(ext) => inbox.join(format!("{stem} ({n}).{}", ext.to_string_lossy())),
+                        None => inbox.join(format!("{stem} ({n})")),
+                    };
+                }
+                Err(e) => return Err(errno(e)),
+            }
+        };
+
+        if let Err(e) = self.tag_file(parent, OsStr::new(name), &path) {
+            let _ = std::fs::remove_file(&path);
+            return Err(e);
+        }
+        Ok((path, file))
+    }
+
+    /// Adds the tags of `parent` to `file`, displaying it as `name`.
+    fn tag_file(
+        &self,
+        parent: &std::path::Path,
+        name: &OsStr,
+        file: &std::path::Path,
+    ) -> Result<(), libc::c_int> {
+        self.check_tag_dir(parent)?;
+        if !file.is_absolute() {
+            return Err(libc::EINVAL);
+        }
+        if !file.exists() {
+            return Err(libc::ENOENT);
+        }
+
+        let name = name.to_string_lossy();
+        let name = name
+            .strip_prefix(self.prefixes().file.as_str())
+            .unwrap_or(&name);
+        let name = (file.file_name() != Some(OsStr::new(name))).then_some(name);
+        let tags = self.path_tags(parent).map(String::from).collect::<Vec<_>>();
+
+        let roots = self.roots()?;
+        let mut db = self.connect_db_mut().map_err(|e| {
+            error!("[tag_file] database connection failed: {e}");
+            libc::EIO
+        })?;
+        match crate::db::add_with(&mut db, &roots, file, tags, name) {
+            Ok(path) => {
+                debug!("[tag_file] tagged {path:?} from {parent:?}");
+                self.invalidate();
+                Ok(())
+            }
+            Err(e) => {
+                error!("[tag_file] {e:#}");
+                Err(libc::EINVAL)
+            }
+        }
+    }
+
+    /// Attributes of a directory owned by the mounting user.
+    fn dir_attr(&self, mtime: SystemTime) -> fusemt::FileAttr {
+        fusemt::FileAttr {
+            size: 0,
+            blocks: 0,
+            atime: mtime,
+            mtime,
+            ctime: mtime,
+            kind: fusemt::FileType::Directory,
+            perm: 0o755,
+            nlink: 2,
+            uid: self.uid,
+            gid: self.gid,
+            rdev: 0,
+
+            // macOS only
+            crtime: mtime,
+            flags: 0,
+        }
+    }
+
+    /// Kind of the file entries, depends on passthrough mode.
+    fn file_kind(&self) -> fusemt::FileType {
+        if self.config.mount_options().passthrough {
+            fusemt::FileType::RegularFile
+        } else {
+            fusemt::FileType::Symlink
+        }
+    }
+
+    /// Attributes of the entry of `file`, taken from the target if it exists.
+    fn file_attr(&self, file: impl AsRef<std::path::Path>) -> fusemt::FileAttr {
+        let Ok(meta) = std::fs::metadata(file) else {
+            return fusemt::FileAttr {
+                kind: self.file_kind(),
+                perm: 0o777,
+                nlink: 1,
+                ..self.dir_attr(UNIX_EPOCH)
+            };
+        };
+        let ctime = UNIX_EPOCH + Duration::new(meta.ctime() as u64, meta.ctime_nsec() as u32);
+        fusemt::FileAttr {
+            size: meta.size(),
+            blocks: meta.blocks(),
+            atime: meta.accessed().unwrap_or(UNIX_EPOCH),
+            mtime: meta.modified().unwrap_or(UNIX_EPOCH),
+            ctime,
+            kind: self.file_kind(),
+            perm: (meta.mode() & 0o7777) as u16,
+            nlink: meta.nlink() as u32,
+            uid: meta.uid(),
+            gid: meta.gid(),
+            rdev: 0,
+
+            // macOS only
+            crtime: meta.created().unwrap_or(UNIX_EPOCH),
+            flags: 0,
+        }
+    }
+
+    /// Current location of the file displayed at `path`.
+    fn real_file(&self, path: &std::path::Path) -> Result<std::path::PathBuf, libc::c_int> {
+        match self.resolve_file(path) {
+            Ok(Some((_, file))) => self.real_path(&file),
+            Ok(None) => Err(libc::EPERM),
+            Err(e) => {
+                error!("[real_file] database connection failed: {e}");
+                Err(libc::EIO)
+            }
+        }
+    }
+
+    /// Opens the real file displayed at `path` with the `open(2)` `flags`.
+    fn open_file(&self, path: &std::path::Path, flags: u32) -> Result<File, libc::c_int> {
+        let file = match self.resolve_file(path) {
+            Ok(Some((_, file))) => self.real_path(&file)?,
+            Ok(None) => return Err(libc::ENOENT),
+            Err(e) => {
+                error!("[open] database connection failed: {e}");
+                return Err(libc::EIO);
+            }
+        };
+        let flags = flags as libc::c_int;
+        let access = flags & libc::O_ACCMODE;
+        std::fs::OpenOptions::new()
+            .read(access == libc::O_RDONLY || access == libc::O_RDWR)
+            .write(access == libc::O_WRONLY || access == libc::O_RDWR)
+            .custom_flags(flags & !libc::O_ACCMODE)
+            .open(file)
+            .map_err(errno)
+    }
+
+    /// Stores an opened file, returning its file handle.
+    fn insert_file(&self, file: File) -> u64 {
+        let fh = self.next_fh.fetch_add(1, Ordering::Relaxed);
+        self.files.lock().unwrap().insert(fh, Arc::new(file));
+        fh
+    }
+
+    fn get_file(&self, fh: u64) -> Result<Arc<File>, libc::c_int> {
+        self.files
+            .lock()
+            .unwrap()
+            .get(&fh)
+            .cloned()
+            .ok_or(libc::EBADF)
+    }
+
+    /// Last modification time of `tag`.
+    fn tag_modified(&self, tag: &OsStr) -> rusqlite::Result<SystemTime> {
+        let db = self.connect_db()?;
+        let mut stmt = db.prepare_cached(sql::TAG_MODIFIED)?;
+        let secs: i64 = stmt.query_row([tag.to_string_lossy()], |r| r.get(0))?;
+        Ok(UNIX_EPOCH + Duration::from_secs(secs.max(0) as u64))
+    }
+
+    /// Attributes of the entry displayed at `path`.
+    fn attr(&self, path: &std::path::Path) -> Result<fusemt::FileAttr, libc::c_int> {
+        let exists = ok_or_panic!(self.name_exists(path), "[attr] database connection failed");
+        match exists {
+            Name::File(_) => match self.resolve_file(path) {
+                Ok(Some((_, file))) => Ok(self.file_attr(self.real_path(&file)?)),
+                Ok(None) => Err(libc::ENOENT),
+                Err(e) => {
+                    error!("[attr] database connection failed: {e}");
+                    Err(libc::EIO)
+                }
+            },
+            Name::Tag(tag) => match self.tag_modified(tag) {
+                Ok(mtime) => Ok(self.dir_attr(mtime)),
+                Err(e) => {
+                    error!("[attr] database connection failed: {e}");
+                    Err(libc::EIO)
+                }
+            },
+            Name::Root => {
+                let mtime = std::fs::metadata(&self.db_path)
+                    .and_then(|m| m.modified())
+                    .unwrap_or(UNIX_EPOCH);
+                Ok(self.dir_attr(mtime))
+            }
+            Name::None => {
+                debug!("[attr] path {path:?} does not exist");
+                Err(libc::ENOENT)
+            }
+        }
+    }
+
+    /// Checks the `access(2)` `mask` of the entry at `path` for the requesting user.
+    fn check_access(
+        &self,
+        req: &fusemt::RequestInfo,
+        path: &std::path::Path,
+        mask: u32,
+    ) -> Result<(), libc::c_int> {
+        let attr = self.attr(path)?;
+        let mask = mask & 0o7;
+        if mask & libc::W_OK as u32 != 0 && self.config.mount_options().read_only {
+            return Err(libc::EROFS);
+        }
+
+        let perm = attr.perm as u32;
+        if req.uid == 0 {
+            // root can do anything but execute files without any execute bit
+            let executable = attr.kind == fusemt::FileType::Directory || perm & 0o111 != 0;
+            if mask & libc::X_OK as u32 != 0 && !executable {
+                return Err(libc::EACCES);
+            }
+            return Ok(());
+        }
+        let granted = if req.uid == attr.uid {
+            perm >> 6
+        } else if req.gid == attr.gid {
+            perm >> 3
+        } else {
+            perm
+        } & 0o7;
+        if granted & mask != mask {
+            return Err(libc::EACCES);
+        }
+        Ok(())
+    }
+}
+
+/// Converts an IO error into the errno returned to FUSE.
+fn errno(e: std::io::Error) -> libc::c_int {
+    e.raw_os_error().unwrap_or(libc::EIO)
+}
+
+/// Returns `value` or its size, following the `getxattr(2)` conventions.
+fn xattr_reply(value: Vec<u8>, size: u32) -> fusemt::ResultXattr {
+    if size == 0 {
+        Ok(fusemt::Xattr::Size(value.len() as u32))
+    } else if value.len() > size as usize {
+        Err(libc::ERANGE)
+    } else {
+        Ok(fusemt::Xattr::Data(value))
     }
 }
 
@@ -147,18 +647,7 @@ impl fusemt::FilesystemMT for Fuse {
     ) -> fusemt::ResultEntry {
         debug!("[getattr] path = {path:?}");
 
-        let exists = ok_or_panic!(
-            self.name_exists(path),
-            "[getattr] database connection failed"
-        );
-        match exists {
-            Name::File(_) => Ok((TTL, LINK_ATTR)),
-            Name::Tag(_) | Name::Root => Ok((TTL, ROOT_DIR_ATTR)),
-            Name::None => {
-                debug!("[getattr] path {path:?} does not exist");
-                Err(libc::ENOENT)
-            }
-        }
+        self.attr(path).map(|attr| (self.ttl(self.attr_ttl), attr))
     }
 
     fn opendir(
@@ -185,69 +674,63 @@ impl fusemt::FilesystemMT for Fuse {
             self.name_exists(path),
             "[readdir] database connection failed"
         );
-        match exists {
-            Name::Tag(_) => {}
-            Name::Root => {
-                let instant = std::time::Instant::now();
-                let entries = self.get_used_tags();
-                info!(
-                    "[readdir::sqlite] root tag query done in {:?}",
-                    instant.elapsed()
-                );
-                return Ok(entries);
-            }
+        let dots = match exists {
+            Name::Tag(_) => [".", ".."].as_slice(),
+            Name::Root => [].as_slice(),
             Name::File(_) => return Err(libc::ENOTDIR),
             Name::None => return Err(libc::ENOENT),
-        }
-
-        let tags = path
-            .components()
-            .skip(1)
-            .flat_map(|c| std::str::from_utf8(c.as_os_str().as_bytes()))
-            .map(|s| &s[1..]);
+        };
 
-        let db = ok_or_panic!(
-            self.connect_db(),
-            "[readdir] failed to connect to sqlite database"
+        let instant = std::time::Instant::now();
+        let listing = self.listing(path).map_err(|e| {
+            error!("[readdir] database connection failed: {e}");
+            libc::EIO
+        })?;
+        info!(
+            "[readdir::sqlite] {path:?} query done in {:?}",
+            instant.elapsed()
         );
-        let mut prep_stmt = db.prepare_cached(&sql::matching_tags_files(tags)).unwrap();
-        let results = prep_stmt
-            .query_map([], |r| {
-                let tag = r.get_ref(0)?.as_str_or_null()?;
-                if let Some(tag) = tag {
-                    let prefix = self.config.tag_prefix();
-                    let tag = format!("{prefix}{tag}");
-                    let kind = fusemt::FileType::Directory;
-                    Ok((tag, kind))
-                } else {
-                    let prefix = self.config.file_prefix();
-                    let file = { r.get_ref(1)?.as_str()? };
-                    let kind = fusemt::FileType::Symlink;
-                    Ok((format!("{prefix}{file}"), kind))
-                }
-            })
-            .unwrap()
-            .flatten()
-            .map(|(s, t)| (OsString::from(s), t));
-
-        let entries = [
-            (std::ffi::OsString::from("."), fusemt::FileType::Directory),
-            (std::ffi::OsString::from(".."), fusemt::FileType::Directory),
-        ]
-        .into_iter()
-        .chain(results)
-        .map(|(name, kind)| fusemt::DirectoryEntry { name, kind })
-        .collect();
+
+        let prefixes = self.prefixes();
+        let results = listing.iter().map(|e| match e {
+            Entry::Tag(tag) => (
+                format!("{}{tag}", prefixes.tag).into(),
+                fusemt::FileType::Directory,
+            ),
+            Entry::File { name, .. } => {
+                (format!("{}{name}", prefixes.file).into(), self.file_kind())
+            }
+        });
+
+        let entries = dots
+            .iter()
+            .map(|d| (OsString::from(d), fusemt::FileType::Directory))
+            .chain(results)
+            .map(|(name, kind)| fusemt::DirectoryEntry { name, kind })
+            .collect();
 
         Ok(entries)
     }
 
     fn init(&self, _req: fuse_mt::RequestInfo) -> fuse_mt::ResultEmpty {
         debug!("[init] initialized filesystem");
+        if !self.config.mount_options().passthrough {
+            info!(
+                "[init] files are symlinks, enable passthrough to use the {XATTR_TAGS}, \
+                {XATTR_PATH} and {XATTR_ID} extended attributes"
+            );
+        }
+        match self.spawn_watcher() {
+            Ok(()) => self.watching.store(true, Ordering::Relaxed),
+            Err(e) => error!("[init] could not watch the database for changes: {e}"),
+        }
         Ok(())
     }
 
     fn destroy(&self) {
+        self.stop_watcher.store(true, Ordering::Relaxed);
+        info!("[destroy] listings cache: {}", self.caches.listings.stats());
+        info!("[destroy] exists cache: {}", self.caches.exists.stats());
         debug!("[destroy] destroyed filesystem");
     }
 
@@ -255,11 +738,16 @@ impl fusemt::FilesystemMT for Fuse {
         &self,
         _req: fuse_mt::RequestInfo,
         path: &std::path::Path,
-        _fh: Option<u64>,
+        fh: Option<u64>,
         mode: u32,
     ) -> fuse_mt::ResultEmpty {
         debug!("[chmod] path = {path:?}, mode = {mode:#o}");
-        Err(libc::ENOSYS)
+        let permissions = std::fs::Permissions::from_mode(mode & 0o7777);
+        match fh {
+            Some(fh) => self.get_file(fh)?.set_permissions(permissions),
+            None => std::fs::set_permissions(self.real_file(path)?, permissions),
+        }
+        .map_err(errno)
     }
 
     fn chown(
@@ -271,30 +759,66 @@ impl fusemt::FilesystemMT for Fuse {
         gid: Option<u32>,
     ) -> fuse_mt::ResultEmpty {
         debug!("[chown] path = {path:?}, uid = {uid:?}, gid = {gid:?}");
-        Err(libc::ENOSYS)
+        std::os::unix::fs::chown(self.real_file(path)?, uid, gid).map_err(errno)
     }
 
     fn truncate(
         &self,
         _req: fuse_mt::RequestInfo,
         path: &std::path::Path,
-        _fh: Option<u64>,
+        fh: Option<u64>,
         size: u64,
     ) -> fuse_mt::ResultEmpty {
         debug!("[truncate] path = {path:?}, size = {size}");
-        Err(libc::ENOSYS)
+        let file = match fh {
+            Some(fh) => self.get_file(fh)?,
+            None => Arc::new(self.open_file(path, libc::O_WRONLY as u32)?),
+        };
+        file.set_len(size).map_err(errno)
     }
 
     fn utimens(
         &self,
         _req: fuse_mt::RequestInfo,
         path: &std::path::Path,
-        _fh: Option<u64>,
-        _atime: Option<std::time::SystemTime>,
-        _mtime: Option<std::time::SystemTime>,
+        fh: Option<u64>,
+        atime: Option<std::time::SystemTime>,
+        mtime: Option<std::time::SystemTime>,
     ) -> fuse_mt::ResultEmpty {
-        debug!("[utimens] path = {path:?}");
-        Err(libc::ENOSYS)
+        debug!("[utimens] path = {path:?}, atime = {atime:?}, mtime = {mtime:?}");
+        if let Some(fh) = fh {
+            let mut times = std::fs::FileTimes::new();
+            if let Some(atime) = atime {
+                times = times.set_accessed(atime);
+            }
+            if let Some(mtime) = mtime {
+                times = times.set_modified(mtime);
+            }
+            return self.get_file(fh)?.set_times(times).map_err(errno);
+        }
+
+        // Without a handle the file may not be readable, so it's not opened
+        let timespec = |time: Option<SystemTime>| match time {
+            Some(time) => {
+                let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+                libc::timespec {
+                    tv_sec: since_epoch.as_secs() as libc::time_t,
+                    tv_nsec: since_epoch.subsec_nanos() as libc::c_long,
+                }
+            }
+            None => libc::timespec {
+                tv_sec: 0,
+                tv_nsec: libc::UTIME_OMIT,
+            },
+        };
+        let times = [timespec(atime), timespec(mtime)];
+        let file = std::ffi::CString::new(self.real_file(path)?.into_os_string().into_vec())
+            .map_err(|_| libc::EINVAL)?;
+        let res = unsafe { libc::utimensat(libc::AT_FDCWD, file.as_ptr(), times.as_ptr(), 0) };
+        if res < 0 {
+            return Err(errno(std::io::Error::last_os_error()));
+        }
+        Ok(())
     }
 
     fn utimens_macos(
@@ -312,14 +836,18 @@ impl fusemt::FilesystemMT for Fuse {
 
     fn readlink(&self, _req: fuse_mt::RequestInfo, path: &std::path::Path) -> fuse_mt::ResultData {
         debug!("[readlink] path = {path:?}");
-        
-        let name = &path.as_os_str().as_bytes()[self.config.file_prefix().len()..];
-        let dup_id = name.iter().position(|&c| c == b'_');
-        if let Some(dup_id) = dup_id {
-            
+        if self.config.mount_options().passthrough {
+            return Err(libc::EINVAL);
         }
 
-        Err(libc::ENOSYS)
+        match self.resolve_file(path) {
+            Ok(Some((_, file))) => Ok(self.real_path(&file)?.into_os_string().into_vec()),
+            Ok(None) => Err(libc::ENOENT),
+            Err(e) => {
+                error!("[readlink] database connection failed: {e}");
+                Err(libc::EIO)
+            }
+        }
     }
 
     fn mknod(
@@ -331,7 +859,16 @@ impl fusemt::FilesystemMT for Fuse {
         _rdev: u32,
     ) -> fuse_mt::ResultEntry {
         debug!("[mknod] parent = {parent:?}, name = {name:?}, mode = {mode:#o}");
-        Err(libc::ENOSYS)
+        if mode & libc::S_IFMT != libc::S_IFREG {
+            return Err(libc::EPERM);
+        }
+        let (path, _) = self.create_file(parent, name, mode, libc::O_WRONLY as u32)?;
+        // The kernel only accepts the requested regular file from mknod, as in create
+        let attr = fusemt::FileAttr {
+            kind: fusemt::FileType::RegularFile,
+            ..self.file_attr(path)
+        };
+        Ok((self.ttl(self.entry_ttl), attr))
     }
 
     fn mkdir(
@@ -373,7 +910,13 @@ impl fusemt::FilesystemMT for Fuse {
         target: &std::path::Path,
     ) -> fuse_mt::ResultEntry {
         debug!("[symlink] parent = {parent:?}, name = {name:?}, target = {target:?}");
-        Err(libc::ENOSYS)
+        self.tag_file(parent, name, target)?;
+        // The kernel only accepts symlinks from symlink, even in passthrough mode
+        let attr = fusemt::FileAttr {
+            kind: fusemt::FileType::Symlink,
+            ..self.file_attr(target)
+        };
+        Ok((self.ttl(self.entry_ttl), attr))
     }
 
     fn rename(
@@ -385,7 +928,59 @@ impl fusemt::FilesystemMT for Fuse {
         newname: &OsStr,
     ) -> fuse_mt::ResultEmpty {
         debug!("[rename] parent = {parent:?}, name = {name:?}, newparent = {newparent:?}, newname = {newname:?}");
-        Err(libc::ENOSYS)
+
+        let file = match self.resolve_file(&parent.join(name)) {
+            Ok(Some((_, file))) => file,
+            Ok(None) => return Err(libc::EPERM),
+            Err(e) => {
+                error!("[rename] database connection failed: {e}");
+                return Err(libc::EIO);
+            }
+        };
+        match self.name_exists(newparent) {
+            Ok(Name::Tag(_)) => {}
+            Ok(Name::Root) => return Err(libc::EPERM),
+            Ok(Name::File(_)) => return Err(libc::ENOTDIR),
+            Ok(Name::None) => return Err(libc::ENOENT),
+            Err(e) => {
+                error!("[rename] database connection failed: {e}");
+                return Err(libc::EIO);
+            }
+        }
+
+        let old_tags = self.path_tags(parent).collect::<Vec<_>>();
+        let new_tags = self.path_tags(newparent).collect::<Vec<_>>();
+        let remove = old_tags
+            .iter()
+            .filter(|t| !new_tags.contains(t))
+            .copied()
+            .collect::<Vec<_>>();
+        let add = new_tags
+            .iter()
+            .filter(|t| !old_tags.contains(t))
+            .copied()
+            .collect::<Vec<_>>();
+
+        let newname = newname.to_string_lossy();
+        let newname = newname
+            .strip_prefix(self.prefixes().file.as_str())
+            .unwrap_or(&newname);
+        let alias = (name
+            .to_string_lossy()
+            .strip_prefix(self.prefixes().file.as_str())
+            != Some(newname))
+        .then_some(newname);
+
+        let mut db = self.connect_db_mut().map_err(|e| {
+            error!("[rename] database connection failed: {e}");
+            libc::EIO
+        })?;
+        crate::db::retag(&mut db, &file, &remove, &add, alias).map_err(|e| {
+            error!("[rename] {e:#}");
+            libc::EINVAL
+        })?;
+        self.invalidate();
+        Ok(())
     }
 
     fn link(
@@ -396,7 +991,16 @@ impl fusemt::FilesystemMT for Fuse {
         newname: &OsStr,
     ) -> fuse_mt::ResultEntry {
         debug!("[link] parent = {path:?}, newparent = {newparent:?}, newname = {newname:?}");
-        Err(libc::ENOSYS)
+        let file = match self.resolve_file(path) {
+            Ok(Some((_, file))) => self.real_path(&file)?,
+            Ok(None) => return Err(libc::ENOENT),
+            Err(e) => {
+                error!("[link] database connection failed: {e}");
+                return Err(libc::EIO);
+            }
+        };
+        self.tag_file(newparent, newname, &file)?;
+        Ok((self.ttl(self.entry_ttl), self.file_attr(file)))
     }
 
     fn open(
@@ -406,27 +1010,43 @@ impl fusemt::FilesystemMT for Fuse {
         flags: u32,
     ) -> fuse_mt::ResultOpen {
         debug!("[open] path = {path:?}, flags = {:?}", flags.to_le_bytes());
-        Err(libc::ENOSYS)
+        let file = self.open_file(path, flags)?;
+        // The second value holds the FOPEN_* reply flags, not the open flags
+        Ok((self.insert_file(file), 0))
     }
 
     fn read(
         &self,
         _req: fuse_mt::RequestInfo,
         path: &std::path::Path,
-        _fh: u64,
+        fh: u64,
         offset: u64,
         size: u32,
         callback: impl FnOnce(fuse_mt::ResultSlice<'_>) -> fuse_mt::CallbackResult,
     ) -> fuse_mt::CallbackResult {
         debug!("[read] path = {path:?}, offset = {offset}, size = {size}");
-        callback(Err(libc::ENOSYS))
+        let file = match self.get_file(fh) {
+            Ok(file) => file,
+            Err(e) => return callback(Err(e)),
+        };
+        let mut buf = vec![0; size as usize];
+        let mut read = 0;
+        while read < buf.len() {
+            match file.read_at(&mut buf[read..], offset + read as u64) {
+                Ok(0) => break,
+                Ok(n) => read += n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return callback(Err(errno(e))),
+            }
+        }
+        callback(Ok(&buf[..read]))
     }
 
     fn write(
         &self,
         _req: fuse_mt::RequestInfo,
         path: &std::path::Path,
-        _fh: u64,
+        fh: u64,
         offset: u64,
         data: Vec<u8>,
         flags: u32,
@@ -436,25 +1056,28 @@ impl fusemt::FilesystemMT for Fuse {
             data.len(),
             flags.to_le_bytes()
         );
-        Err(libc::ENOSYS)
+        let file = self.get_file(fh)?;
+        file.write_all_at(&data, offset).map_err(errno)?;
+        Ok(data.len() as u32)
     }
 
     fn flush(
         &self,
         _req: fuse_mt::RequestInfo,
         path: &std::path::Path,
-        _fh: u64,
+        fh: u64,
         _lock_owner: u64,
     ) -> fuse_mt::ResultEmpty {
         debug!("[flush] path = {path:?}");
-        Err(libc::ENOSYS)
+        // Writes go straight to the real file, nothing is buffered
+        self.get_file(fh).map(|_| ())
     }
 
     fn release(
         &self,
         _req: fuse_mt::RequestInfo,
         path: &std::path::Path,
-        _fh: u64,
+        fh: u64,
         flags: u32,
         _lock_owner: u64,
         flush: bool,
@@ -463,18 +1086,24 @@ impl fusemt::FilesystemMT for Fuse {
             "[release] path = {path:?}, flags = {:?}, flush = {flush}",
             flags.to_le_bytes()
         );
-        Err(libc::ENOSYS)
+        self.files.lock().unwrap().remove(&fh);
+        Ok(())
     }
 
     fn fsync(
         &self,
         _req: fuse_mt::RequestInfo,
         path: &std::path::Path,
-        _fh: u64,
-        _datasync: bool,
+        fh: u64,
+        datasync: bool,
     ) -> fuse_mt::ResultEmpty {
-        debug!("[fsync] path = {path:?}");
-        Err(libc::ENOSYS)
+        debug!("[fsync] path = {path:?}, datasync = {datasync}");
+        let file = self.get_file(fh)?;
+        if datasync {
+            file.sync_data().map_err(errno)
+        } else {
+            file.sync_all().map_err(errno)
+        }
     }
 
     fn releasedir(
@@ -504,61 +1133,160 @@ impl fusemt::FilesystemMT for Fuse {
 
     fn statfs(&self, _req: fuse_mt::RequestInfo, path: &std::path::Path) -> fuse_mt::ResultStatfs {
         debug!("[statfs] path = {path:?}");
-        Err(libc::ENOSYS)
+
+        let (files, tags) = self
+            .connect_db()
+            .and_then(|db| {
+                db.query_row(sql::COUNT_FILES_TAGS, [], |r| {
+                    Ok((r.get::<_, u64>(0)?, r.get::<_, u64>(1)?))
+                })
+            })
+            .map_err(|e| {
+                error!("[statfs] database connection failed: {e}");
+                libc::EIO
+            })?;
+        let db_size = std::fs::metadata(&self.db_path).map_err(errno)?.len();
+
+        // Free space is taken from the filesystem where the database is stored
+        let db_path = std::ffi::CString::new(self.db_path.as_os_str().as_bytes())
+            .map_err(|_| libc::EINVAL)?;
+        let mut stat = unsafe { std::mem::zeroed::<libc::statvfs>() };
+        if unsafe { libc::statvfs(db_path.as_ptr(), &mut stat) } < 0 {
+            return Err(errno(std::io::Error::last_os_error()));
+        }
+
+        let bsize = stat.f_frsize.max(1);
+        let used = db_size.div_ceil(bsize);
+        Ok(fusemt::Statfs {
+            blocks: used + stat.f_bavail,
+            bfree: stat.f_bavail,
+            bavail: stat.f_bavail,
+            files: files + tags + stat.f_favail,
+            ffree: stat.f_favail,
+            bsize: bsize as u32,
+            namelen: 255,
+            frsize: bsize as u32,
+        })
     }
 
     fn setxattr(
         &self,
         _req: fuse_mt::RequestInfo,
         path: &std::path::Path,
-        _name: &OsStr,
-        _value: &[u8],
+        name: &OsStr,
+        value: &[u8],
         _flags: u32,
         _position: u32,
     ) -> fuse_mt::ResultEmpty {
-        debug!("[setxattr] path = {path:?}");
-        Err(libc::ENOSYS)
+        debug!("[setxattr] path = {path:?}, name = {name:?}");
+        let file = match self.resolve_file(path) {
+            Ok(Some((_, file))) => file,
+            Ok(None) => return Err(libc::ENOTSUP),
+            Err(e) => {
+                error!("[setxattr] database connection failed: {e}");
+                return Err(libc::EIO);
+            }
+        };
+        match name.to_str() {
+            Some(XATTR_TAGS) => {}
+            Some(XATTR_PATH | XATTR_ID) => return Err(libc::EPERM),
+            _ => return Err(libc::ENOTSUP),
+        }
+
+        let value = std::str::from_utf8(value).map_err(|_| libc::EINVAL)?;
+        let tags = value
+            .split(',')
+            .map(str::trim)
+            .filter(|t| !t.is_empty())
+            .collect::<Vec<_>>();
+        let mut db = self.connect_db_mut().map_err(|e| {
+            error!("[setxattr] database connection failed: {e}");
+            libc::EIO
+        })?;
+        crate::db::set_tags(&mut db, &file, &tags).map_err(|e| {
+            error!("[setxattr] {e:#}");
+            libc::EIO
+        })?;
+        self.invalidate();
+        Ok(())
     }
 
     fn getxattr(
         &self,
         _req: fuse_mt::RequestInfo,
         path: &std::path::Path,
-        _name: &OsStr,
-        _size: u32,
+        name: &OsStr,
+        size: u32,
     ) -> fuse_mt::ResultXattr {
-        debug!("[getxattr] path = {path:?}");
-        Err(libc::ENOSYS)
+        debug!("[getxattr] path = {path:?}, name = {name:?}, size = {size}");
+        let (id, file) = match self.resolve_file(path) {
+            Ok(Some(file)) => file,
+            Ok(None) => return Err(libc::ENODATA),
+            Err(e) => {
+                error!("[getxattr] database connection failed: {e}");
+                return Err(libc::EIO);
+            }
+        };
+        let value = match name.to_str() {
+            Some(XATTR_TAGS) => {
+                let tags = self
+                    .connect_db()
+                    .and_then(|db| crate::db::file_tags(&db, &file))
+                    .map_err(|e| {
+                        error!("[getxattr] database connection failed: {e}");
+                        libc::EIO
+                    })?;
+                tags.join(",")
+            }
+            Some(XATTR_PATH) => self.real_path(&file)?.to_string_lossy().into_owned(),
+            Some(XATTR_ID) => id.to_string(),
+            _ => return Err(libc::ENODATA),
+        };
+        xattr_reply(value.into_bytes(), size)
     }
 
     fn listxattr(
         &self,
         _req: fuse_mt::RequestInfo,
         path: &std::path::Path,
-        _size: u32,
+        size: u32,
     ) -> fuse_mt::ResultXattr {
-        debug!("[listxattr] path = {path:?}");
-        Err(libc::ENOSYS)
+        debug!("[listxattr] path = {path:?}, size = {size}");
+        let names = match self.resolve_file(path) {
+            Ok(Some(_)) => [XATTR_TAGS, XATTR_PATH, XATTR_ID]
+                .into_iter()
+                .flat_map(|n| n.bytes().chain([0]))
+                .collect(),
+            Ok(None) => Vec::new(),
+            Err(e) => {
+                error!("[listxattr] database connection failed: {e}");
+                return Err(libc::EIO);
+            }
+        };
+        xattr_reply(names, size)
     }
 
     fn removexattr(
         &self,
         _req: fuse_mt::RequestInfo,
         path: &std::path::Path,
-        _name: &OsStr,
+        name: &OsStr,
     ) -> fuse_mt::ResultEmpty {
-        debug!("[removexattr] path = {path:?}");
-        Err(libc::ENOSYS)
+        debug!("[removexattr] path = {path:?}, name = {name:?}");
+        match name.to_str() {
+            Some(XATTR_TAGS | XATTR_PATH | XATTR_ID) => Err(libc::EPERM),
+            _ => Err(libc::ENODATA),
+        }
     }
 
     fn access(
         &self,
-        _req: fuse_mt::RequestInfo,
+        req: fuse_mt::RequestInfo,
         path: &std::path::Path,
-        _mask: u32,
+        mask: u32,
     ) -> fuse_mt::ResultEmpty {
-        debug!("[access] path = {path:?}");
-        Err(libc::ENOSYS)
+        debug!("[access] path = {path:?}, mask = {mask:#o}");
+        self.check_access(&req, path, mask)
     }
 
     fn create(
@@ -566,10 +1294,207 @@ impl fusemt::FilesystemMT for Fuse {
         _req: fuse_mt::RequestInfo,
         parent: &std::path::Path,
         name: &OsStr,
-        _mode: u32,
-        _flags: u32,
+        mode: u32,
+        flags: u32,
     ) -> fuse_mt::ResultCreate {
-        debug!("[create] parent = {parent:?}, name = {name:?}");
-        Err(libc::ENOSYS)
+        debug!(
+            "[create] parent = {parent:?}, name = {name:?}, mode = {mode:#o}, flags = {flags:#o}"
+        );
+        let (path, file) = self.create_file(parent, name, mode, flags)?;
+        Ok(fusemt::CreatedEntry {
+            ttl: self.ttl(self.entry_ttl),
+            // The kernel only accepts regular files from create
+            attr: fusemt::FileAttr {
+                kind: fusemt::FileType::RegularFile,
+                ..self.file_attr(path)
+            },
+            fh: self.insert_file(file),
+            // FOPEN_* reply flags, as in open
+            flags: 0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fusemt::FilesystemMT;
+    use std::path::{Path, PathBuf};
+
+    /// Filesystem on a new database in a temporary directory, with `tagged` tagged `a`.
+    fn mount(name: &str, passthrough: bool) -> (Fuse, PathBuf) {
+        mount_db(name, passthrough, "db.sqlite")
+    }
+
+    /// Like [`mount`], with the database at `db_file` inside the temporary directory.
+    fn mount_db(name: &str, passthrough: bool, db_file: &str) -> (Fuse, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("tg-fuse-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let dir = dir.canonicalize().unwrap();
+        let overrides = crate::config::Overrides {
+            config: Some(dir.join("config.toml")),
+            db: Some(dir.join(db_file)),
+        };
+        std::fs::create_dir_all(dir.join(db_file).parent().unwrap()).unwrap();
+        let mut config = Config::load_with(None, &overrides).unwrap();
+        config.mount_options_mut().passthrough = passthrough;
+
+        let mut db = rusqlite::Connection::open(config.db_path()).unwrap();
+        sql::migrate(&mut db).unwrap();
+        std::fs::write(dir.join("tagged"), "").unwrap();
+        crate::db::add(&mut db, dir.join("tagged"), ["a".to_string()], None).unwrap();
+
+        (Fuse::new(dir.join(db_file), config), dir)
+    }
+
+    fn req() -> fusemt::RequestInfo {
+        fusemt::RequestInfo {
+            unique: 0,
+            uid: unsafe { libc::getuid() },
+            gid: unsafe { libc::getgid() },
+            pid: 0,
+        }
+    }
+
+    #[test]
+    fn created_files_resolve_without_prefix() {
+        let (fuse, dir) = mount("create", false);
+        let created = fuse
+            .create(req(), Path::new("/:a"), OsStr::new("note.md"), 0o644, 0)
+            .unwrap();
+        assert_eq!(created.flags, 0);
+        assert_eq!(created.attr.kind, fusemt::FileType::RegularFile);
+
+        let real = dir.join("inbox").join("note.md");
+        for path in ["/:a/note.md", "/:a/_note.md"] {
+            assert!(fuse.getattr(req(), Path::new(path), None).is_ok(), "{path}");
+            assert_eq!(fuse.real_file(Path::new(path)), Ok(real.clone()));
+        }
+        assert_eq!(
+            fuse.getattr(req(), Path::new("/:a/missing.md"), None)
+                .map(|_| ()),
+            Err(libc::ENOENT)
+        );
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn project_files_are_stored_relative_to_the_root() {
+        let (fuse, dir) = mount_db("project", false, ".tg/db.sqlite");
+        fuse.create(req(), Path::new("/:a"), OsStr::new("note.md"), 0o644, 0)
+            .unwrap();
+
+        let db = rusqlite::Connection::open(dir.join(".tg/db.sqlite")).unwrap();
+        let stored: String = db
+            .query_row("SELECT path FROM Files WHERE name = 'note.md'", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(stored, ".tg/inbox/note.md");
+        assert_eq!(
+            fuse.real_file(Path::new("/:a/note.md")),
+            Ok(dir.join(".tg/inbox/note.md"))
+        );
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn chmod_and_utimens_change_the_real_file() {
+        let (fuse, dir) = mount("setattr", false);
+        let path = Path::new("/:a/_tagged");
+        let real = dir.join("tagged");
+
+        fuse.chmod(req(), path, None, 0o600).unwrap();
+        let meta = std::fs::metadata(&real).unwrap();
+        assert_eq!(meta.mode() & 0o7777, 0o600);
+
+        let mtime = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        fuse.utimens(req(), path, None, None, Some(mtime)).unwrap();
+        let meta = std::fs::metadata(&real).unwrap();
+        assert_eq!(meta.modified().unwrap(), mtime);
+        assert_ne!(meta.accessed().unwrap(), mtime);
+
+        let (fh, _) = fuse.open(req(), path, libc::O_RDONLY as u32).unwrap();
+        let atime = UNIX_EPOCH + Duration::from_secs(2_000_000);
+        fuse.utimens(req(), path, Some(fh), Some(atime), None)
+            .unwrap();
+        let meta = std::fs::metadata(&real).unwrap();
+        assert_eq!(meta.accessed().unwrap(), atime);
+        assert_eq!(meta.modified().unwrap(), mtime);
+
+        assert_eq!(
+            fuse.chmod(req(), Path::new("/:a"), None, 0o700),
+            Err(libc::EPERM)
+        );
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn xattrs_of_passthrough_files() {
+        let (fuse, dir) = mount("xattr", true);
+        let path = Path::new("/:a/_tagged");
+        let (_, attr) = fuse.getattr(req(), path, None).unwrap();
+        assert_eq!(attr.kind, fusemt::FileType::RegularFile);
+
+        let get = |name: &str| match fuse.getxattr(req(), path, OsStr::new(name), 4096) {
+            Ok(fusemt::Xattr::Data(data)) => Ok(String::from_utf8(data).unwrap()),
+            Ok(fusemt::Xattr::Size(_)) => panic!("size returned for a buffer"),
+            Err(e) => Err(e),
+        };
+        assert_eq!(get(XATTR_TAGS), Ok("a".to_string()));
+        assert_eq!(
+            get(XATTR_PATH),
+            Ok(dir.join("tagged").to_string_lossy().into_owned())
+        );
+        assert!(get(XATTR_ID).unwrap().parse::<i64>().is_ok());
+        assert_eq!(get("user.other"), Err(libc::ENODATA));
+        assert!(matches!(
+            fuse.getxattr(req(), path, OsStr::new(XATTR_TAGS), 0),
+            Ok(fusemt::Xattr::Size(1))
+        ));
+        assert!(matches!(
+            fuse.listxattr(req(), path, 4096),
+            Ok(fusemt::Xattr::Data(names)) if names == b"user.tg.tags\0user.tg.path\0user.tg.id\0"
+        ));
+
+        fuse.setxattr(req(), path, OsStr::new(XATTR_TAGS), b"a, b", 0, 0)
+            .unwrap();
+        assert_eq!(get(XATTR_TAGS), Ok("a,b".to_string()));
+        assert!(fuse.getattr(req(), Path::new("/:b/_tagged"), None).is_ok());
+        assert_eq!(
+            fuse.setxattr(req(), path, OsStr::new(XATTR_PATH), b"/x", 0, 0),
+            Err(libc::EPERM)
+        );
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn files_are_symlinks_without_passthrough() {
+        let (fuse, dir) = mount("symlink", false);
+        let path = Path::new("/:a/_tagged");
+        let (_, attr) = fuse.getattr(req(), path, None).unwrap();
+        // So the kernel never forwards the extended attributes of files
+        assert_eq!(attr.kind, fusemt::FileType::Symlink);
+        assert_eq!(
+            fuse.readlink(req(), path),
+            Ok(dir.join("tagged").into_os_string().into_vec())
+        );
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn ttls_are_capped_only_when_watching() {
+        let (mut fuse, dir) = mount("ttl", false);
+        let ttl = Duration::from_secs(5);
+        fuse.watching.store(true, Ordering::Relaxed);
+        assert_eq!(fuse.ttl(ttl), ttl);
+
+        fuse.config.mount_options_mut().watch = true;
+        assert_eq!(fuse.ttl(ttl), WATCH_INTERVAL);
+        assert_eq!(fuse.ttl(Duration::ZERO), Duration::ZERO);
+        fuse.watching.store(false, Ordering::Relaxed);
+        assert_eq!(fuse.ttl(ttl), ttl);
+        let _ = std::fs::remove_dir_all(dir);
     }
 }
@@ -0,0 +1,758 @@
+use anyhow::{bail, Context, Result};
+use log::debug;
+use rusqlite::OptionalExtension;
+use serde::de::{DeserializeSeed, Error as _, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use std::{
+    io::{BufRead, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    export::{File, Format, Tag},
+    roots::Roots,
+    sql,
+};
+
+/// How imported files are merged with the registered ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Only adds tags, never removes them
+    Add,
+    /// Sets the tags of each imported file to the imported ones
+    Replace,
+    /// Like `Replace`, also removing the files and tags that weren't imported
+    Mirror,
+}
+
+impl std::str::FromStr for Mode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "add" => Ok(Mode::Add),
+            "replace" => Ok(Mode::Replace),
+            "mirror" => Ok(Mode::Mirror),
+            _ => bail!("unknown mode {s:?}, expected add, replace or mirror"),
+        }
+    }
+}
+
+impl std::fmt::Display for Mode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Mode::Add => write!(f, "add"),
+            Mode::Replace => write!(f, "replace"),
+            Mode::Mirror => write!(f, "mirror"),
+        }
+    }
+}
+
+/// Replaces the `from` prefix of imported paths with `to`, written as `FROM=TO`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rewrite {
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+impl std::str::FromStr for Rewrite {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once('=') {
+            Some((from, to)) if !from.is_empty() && !to.is_empty() => Ok(Rewrite {
+                from: from.into(),
+                to: to.into(),
+            }),
+            _ => bail!("invalid rewrite {s:?}, expected FROM=TO"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub mode: Mode,
+    /// Applied to each path in order, only the first matching one is used
+    pub rewrites: Vec<Rewrite>,
+    /// Rolls back the changes instead of committing them
+    pub dry_run: bool,
+}
+
+impl Options {
+    fn rewrite(&self, path: &Path) -> PathBuf {
+        self.rewrites
+            .iter()
+            .find_map(|r| Some(r.to.join(path.strip_prefix(&r.from).ok()?)))
+            .unwrap_or_else(|| path.to_path_buf())
+    }
+}
+
+/// Number of files and tags changed by an import.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Summary {
+    pub added: u64,
+    pub changed: u64,
+    pub unchanged: u64,
+    pub removed: u64,
+    pub tags_added: usize,
+    pub tags_removed: usize,
+}
+
+impl std::fmt::Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} files added, {} changed, {} unchanged and {} removed; {} tags added and {} removed",
+            self.added,
+            self.changed,
+            self.unchanged,
+            self.removed,
+            self.tags_added,
+            self.tags_removed
+        )
+    }
+}
+
+/// An element of an imported file, read one at a time.
+pub enum Entry {
+    Tag(Tag),
+    File(File),
+}
+
+/// Visits the top-level object of a JSON export, calling the callback with each entry.
+struct Document<'a, F>(&'a mut F);
+
+/// Visits a JSON array, calling the callback with each element.
+struct Entries<'a, F, T>(&'a mut F, fn(T) -> Entry);
+
+impl<'de, F: FnMut(Entry) -> Result<()>> DeserializeSeed<'de> for Document<'_, F> {
+    type Value = ();
+
+    fn deserialize<D>(self, deserializer: D) -> std::result::Result<(), D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, F: FnMut(Entry) -> Result<()>> Visitor<'de> for Document<'_, F> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "an object with tags and files")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> std::result::Result<(), A::Error> {
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "tags" => map.next_value_seed(Entries(&mut *self.0, Entry::Tag))?,
+                "files" => map.next_value_seed(Entries(&mut *self.0, Entry::File))?,
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'de, F, T> DeserializeSeed<'de> for Entries<'_, F, T>
+where
+    F: FnMut(Entry) -> Result<()>,
+    T: serde::Deserialize<'de>,
+{
+    type Value = ();
+
+    fn deserialize<D>(self, deserializer: D) -> std::result::Result<(), D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, F, T> Visitor<'de> for Entries<'_, F, T>
+where
+    F: FnMut(Entry) -> Result<()>,
+    T: serde::Deserialize<'de>,
+{
+    type Value = ();
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "an array")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> std::result::Result<(), A::Error> {
+        while let Some(element) = seq.next_element::<T>()? {
+            (self.0)((self.1)(element)).map_err(|e| A::Error::custom(format!("{e:#}")))?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads the next CSV record into `fields`, which can span several lines if quoted.
+///
+/// Returns `false` at the end of the input.
+fn read_record(input: &mut impl BufRead, fields: &mut Vec<String>) -> Result<bool> {
+    fields.clear();
+    let mut line = String::new();
+    if input.read_line(&mut line)? == 0 {
+        return Ok(false);
+    }
+    let mut field = String::new();
+    let mut quoted = false;
+    loop {
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' if quoted && chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' if quoted => quoted = false,
+                '"' if field.is_empty() => quoted = true,
+                ',' if !quoted => fields.push(std::mem::take(&mut field)),
+                '\n' | '\r' if !quoted => {}
+                c => field.push(c),
+            }
+        }
+        if !quoted {
+            break;
+        }
+        line.clear();
+        if input.read_line(&mut line)? == 0 {
+            bail!("unterminated quoted field {field:?}");
+        }
+    }
+    fields.push(field);
+    Ok(true)
+}
+
+/// Calls `f` with every entry of `input`, grouping consecutive CSV rows of the same file.
+fn read(
+    mut input: impl BufRead,
+    format: Format,
+    mut f: impl FnMut(Entry) -> Result<()>,
+) -> Result<()> {
+    match format {
+        Format::Json => {
+            let mut deserializer = serde_json::Deserializer::from_reader(input);
+            Document(&mut f).deserialize(&mut deserializer)?;
+            deserializer.end()?;
+        }
+        Format::Csv => {
+            let mut current: Option<File> = None;
+            let mut fields = Vec::new();
+            let mut record = 0;
+            while read_record(&mut input, &mut fields)? {
+                record += 1;
+                let [path, tag] = fields.as_slice() else {
+                    bail!(
+                        "record {record} has {} fields, expected path,tag",
+                        fields.len()
+                    );
+                };
+                if record == 1 && path == "path" && tag == "tag" {
+                    continue;
+                }
+                match &mut current {
+                    Some(file) if file.path.as_os_str() == path.as_str() => {}
+                    _ => {
+                        let file = File {
+                            path: path.into(),
+                            name: None,
+                            tags: Vec::new(),
+                        };
+                        if let Some(file) = current.replace(file) {
+                            f(Entry::File(file))?;
+                        }
+                    }
+                }
+                if let Some(file) = &mut current {
+                    if !tag.is_empty() {
+                        file.tags.push(tag.clone());
+                    }
+                }
+            }
+            if let Some(file) = current {
+                f(Entry::File(file))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Applies the imported entries to the database inside a transaction.
+struct Importer<'a, 'w> {
+    tx: &'a rusqlite::Connection,
+    roots: Roots,
+    options: &'a Options,
+    diff: Option<&'w mut dyn Write>,
+    /// Whether the entries carry the display names, otherwise the current ones are kept
+    names: bool,
+    summary: Summary,
+}
+
+impl Importer<'_, '_> {
+    fn tag(&mut self, tag: Tag) -> Result<()> {
+        if tag.tag.is_empty() {
+            bail!("tags cannot be empty");
+        }
+        let tx = self.tx;
+        tx.prepare_cached(sql::INSERT_IMPORTED_TAG)?
+            .execute([&tag.tag])?;
+        self.summary.tags_added += tx.prepare_cached(sql::INSERT_TAG)?.execute([&tag.tag])?;
+        Ok(())
+    }
+
+    fn file(&mut self, file: File) -> Result<()> {
+        let path = self.options.rewrite(&file.path);
+        if !path.is_absolute() {
+            bail!("the path {path:?} is not absolute");
+        }
+        if let Some(name) = path.file_name() {
+            crate::db::validate_name(&name.to_string_lossy())?;
+        }
+        if let Some(name) = &file.name {
+            crate::db::validate_name(name)?;
+        }
+        let mut tags = file.tags;
+        if tags.iter().any(String::is_empty) {
+            bail!("the file {path:?} has an empty tag");
+        }
+        tags.sort_unstable();
+        tags.dedup();
+
+        let tx = self.tx;
+        let stored = self.roots.store(&path);
+        debug!("Importing {stored:?} : {tags:?}");
+        let new = tx.prepare_cached(sql::INSERT_FILE)?.execute([&stored])? == 1;
+        // Files repeated in the import keep the tags of their previous entries
+        let repeated = tx
+            .prepare_cached(sql::INSERT_IMPORTED_FILE)?
+            .execute([&stored])?
+            == 0;
+        let current = crate::db::file_tags(tx, &stored)?;
+
+        let add = tags
+            .iter()
+            .filter(|t| !current.contains(t))
+            .collect::<Vec<_>>();
+        let remove = match self.options.mode {
+            Mode::Replace | Mode::Mirror if !repeated => current
+                .iter()
+                .filter(|t| !tags.contains(t))
+                .collect::<Vec<_>>(),
+            _ => Vec::new(),
+        };
+        // Without an alias the name is reset to the file name, unless only adding
+        // or the format can't carry aliases
+        let name = match (&file.name, self.options.mode) {
+            (Some(name), _) => Some(name.clone()),
+            (None, Mode::Add) => None,
+            (None, _) if !self.names => None,
+            (None, _) => path.file_name().map(|n| n.to_string_lossy().into_owned()),
+        };
+        let current_name = tx
+            .prepare_cached(sql::FILE_NAME)?
+            .query_row([&stored], |r| r.get::<_, Option<String>>(0))
+            .optional()?
+            .flatten();
+        let rename = name.filter(|n| current_name.as_ref() != Some(n));
+
+        let mut delete_filetag_stmt = tx.prepare_cached(sql::DELETE_FILETAG)?;
+        for tag in &remove {
+            delete_filetag_stmt
+                .execute([&stored, *tag])
+                .context("could not delete filetag")?;
+        }
+        let mut insert_tag_stmt = tx.prepare_cached(sql::INSERT_TAG)?;
+        let mut insert_filetag_stmt = tx.prepare_cached(sql::INSERT_FILETAG)?;
+        for tag in &add {
+            self.summary.tags_added += insert_tag_stmt
+                .execute([tag])
+                .context("could not insert tag")?;
+            insert_filetag_stmt
+                .execute([&stored, *tag])
+                .context("could not insert filetag")?;
+        }
+        if let Some(name) = &rename {
+            tx.prepare_cached(sql::SET_FILE_NAME)?
+                .execute([&stored, name])
+                .context("could not set file name")?;
+        }
+
+        let changes = add
+            .iter()
+            .map(|t| format!(" +{t}"))
+            .chain(remove.iter().map(|t| format!(" -{t}")))
+            .chain(rename.iter().map(|n| format!(" as {n:?}")))
+            .collect::<String>();
+        if new {
+            self.summary.added += 1;
+            self.write_diff(format_args!("+ {path:?}{changes}"))?;
+        } else if !changes.is_empty() {
+            self.summary.changed += 1;
+            self.write_diff(format_args!("~ {path:?}{changes}"))?;
+        } else if !repeated {
+            self.summary.unchanged += 1;
+        }
+        Ok(())
+    }
+
+    /// Removes the files and tags that weren't imported.
+    fn mirror(&mut self) -> Result<()> {
+        let tx = self.tx;
+        let paths = tx
+            .prepare(sql::NOT_IMPORTED_FILES)?
+            .query_map([], |r| r.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        for stored in paths {
+            let tags = crate::db::file_tags(tx, &stored)?;
+            tx.prepare_cached(sql::DELETE_FILE_TAGS)?
+                .execute([&stored])?;
+            tx.prepare_cached(sql::DELETE_FILE)?.execute([&stored])?;
+
+            self.summary.removed += 1;
+            let path = self.roots.resolve(&stored).unwrap_or(stored.into());
+            let changes = tags.iter().map(|t| format!(" -{t}")).collect::<String>();
+            self.write_diff(format_args!("- {path:?}{changes}"))?;
+        }
+        self.summary.tags_removed = tx.execute(sql::DELETE_NOT_IMPORTED_TAGS, [])?;
+        Ok(())
+    }
+
+    fn write_diff(&mut self, line: std::fmt::Arguments) -> Result<()> {
+        if let Some(diff) = &mut self.diff {
+            writeln!(diff, "{line}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Merges the tags read from `input` into the database at `db_path` in a single transaction.
+///
+/// Each changed file is written to `diff` as `+ "path" +tag` if added, `~ "path" +tag -tag` if changed
+/// or `- "path" -tag` if removed.
+/// The modification time of the imported tags is ignored, as they are changed now.
+pub fn import(
+    db: &mut rusqlite::Connection,
+    db_path: &Path,
+    input: impl BufRead,
+    format: Format,
+    options: &Options,
+    diff: Option<&mut dyn Write>,
+) -> Result<Summary> {
+    import_entries(db, db_path, options, diff, format.has_names(), |f| {
+        read(input, format, f)
+    })
+}
+
+/// Merges the entries that `read` passes to its callback like [`import`].
+///
+/// If the entries can't carry display names (`names` is `false`), the current ones are kept.
+pub fn import_entries(
+    db: &mut rusqlite::Connection,
+    db_path: &Path,
+    options: &Options,
+    diff: Option<&mut dyn Write>,
+    names: bool,
+    read: impl FnOnce(&mut dyn FnMut(Entry) -> Result<()>) -> Result<()>,
+) -> Result<Summary> {
+    let tx = db.transaction()?;
+    tx.execute_batch(sql::CREATE_IMPORTED)?;
+    let mut importer = Importer {
+        tx: &tx,
+        roots: Roots::load_for(&tx, db_path)?,
+        options,
+        diff,
+        names,
+        summary: Summary::default(),
+    };
+    read(&mut |entry| match entry {
+        Entry::Tag(tag) => importer.tag(tag),
+        Entry::File(file) => importer.file(file),
+    })
+    .context("could not import")?;
+    if options.mode == Mode::Mirror {
+        importer.mirror()?;
+    }
+    if let Some(diff) = &mut importer.diff {
+        diff.flush()?;
+    }
+    let summary = importer.summary;
+    tx.execute_batch(sql::DROP_IMPORTED)?;
+
+    if !options.dry_run {
+        tx.commit()?;
+    }
+    Ok(summary)
+}
+
+/// Opens `path` for reading, or stdin if `None`.
+pub fn input(path: Option<&Path>) -> Result<Box<dyn BufRead>> {
+    Ok(match path {
+        Some(path) => Box::new(std::io::BufReader::new(
+            std::fs::File::open(path).with_context(|| format!("could not open {path:?}"))?,
+        )),
+        None => Box::new(std::io::stdin().lock()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records(input: &str) -> Result<Vec<Vec<String>>> {
+        let mut input = input.as_bytes();
+        let mut fields = Vec::new();
+        let mut records = Vec::new();
+        while read_record(&mut input, &mut fields)? {
+            records.push(fields.clone());
+        }
+        Ok(records)
+    }
+
+    #[test]
+    fn read_records() {
+        assert_eq!(
+            records("path,tag\n/a,t1\r\n/b,\n").unwrap(),
+            [["path", "tag"], ["/a", "t1"], ["/b", ""]]
+        );
+        assert_eq!(
+            records(r#""/a,b","say ""hi""""#).unwrap(),
+            [["/a,b", r#"say "hi""#]]
+        );
+        assert_eq!(
+            records("\"/multi\nline\r\n\",t\n/c,t\n").unwrap(),
+            [["/multi\nline\r\n", "t"], ["/c", "t"]]
+        );
+        assert!(records("").unwrap().is_empty());
+        assert!(records("\"/a,t\n/b,t\n").is_err());
+    }
+
+    #[test]
+    fn csv_round_trip() {
+        let fields = [
+            "/plain",
+            "/with,comma",
+            r#"/with "quotes""#,
+            "/with\nnew\r\nlines",
+            r#"","#,
+            "",
+        ];
+        let line = fields
+            .iter()
+            .map(|f| crate::export::csv_field(f))
+            .collect::<Vec<_>>()
+            .join(",");
+        assert_eq!(records(&format!("{line}\n")).unwrap(), [fields]);
+    }
+
+    /// Database with `/a` aliased `alias` and tagged `x`, and `/b` tagged `x` and `y`.
+    fn db() -> rusqlite::Connection {
+        let mut db = rusqlite::Connection::open_in_memory().unwrap();
+        sql::migrate(&mut db).unwrap();
+        let json = r#"{"tags": [], "files": [
+            {"path": "/a", "name": "alias", "tags": ["x"]},
+            {"path": "/b", "tags": ["x", "y"]}
+        ]}"#;
+        let summary = run(&mut db, json, Format::Json, Mode::Add, false);
+        assert_eq!(
+            summary,
+            Summary {
+                added: 2,
+                tags_added: 2,
+                ..Default::default()
+            }
+        );
+        db
+    }
+
+    fn run(
+        db: &mut rusqlite::Connection,
+        input: &str,
+        format: Format,
+        mode: Mode,
+        dry_run: bool,
+    ) -> Summary {
+        let options = Options {
+            mode,
+            rewrites: Vec::new(),
+            dry_run,
+        };
+        import(
+            db,
+            Path::new(":memory:"),
+            input.as_bytes(),
+            format,
+            &options,
+            None,
+        )
+        .unwrap()
+    }
+
+    fn state(db: &rusqlite::Connection) -> Vec<(String, Option<String>, Vec<String>)> {
+        crate::db::files(db, &[])
+            .unwrap()
+            .into_iter()
+            .map(|path| {
+                let name = db.query_row(sql::FILE_NAME, [&path], |r| r.get(0)).unwrap();
+                let tags = crate::db::file_tags(db, &path).unwrap();
+                (path, name, tags)
+            })
+            .collect()
+    }
+
+    fn file(
+        path: &str,
+        name: Option<&str>,
+        tags: &[&str],
+    ) -> (String, Option<String>, Vec<String>) {
+        (
+            path.to_string(),
+            name.map(String::from),
+            tags.iter().map(|t| t.to_string()).collect(),
+        )
+    }
+
+    #[test]
+    fn import_adding() {
+        let mut db = db();
+        let summary = run(&mut db, "/a,z\n/c,x\n", Format::Csv, Mode::Add, false);
+        assert_eq!(
+            summary,
+            Summary {
+                added: 1,
+                changed: 1,
+                tags_added: 1,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            state(&db),
+            [
+                file("/a", Some("alias"), &["x", "z"]),
+                file("/b", Some("b"), &["x", "y"]),
+                file("/c", Some("c"), &["x"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn csv_replace_keeps_aliases() {
+        let mut db = db();
+        let summary = run(
+            &mut db,
+            "/a,y\n/b,x\n/b,y\n",
+            Format::Csv,
+            Mode::Replace,
+            false,
+        );
+        assert_eq!(
+            summary,
+            Summary {
+                changed: 1,
+                unchanged: 1,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            state(&db),
+            [
+                file("/a", Some("alias"), &["y"]),
+                file("/b", Some("b"), &["x", "y"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn json_replace_resets_aliases() {
+        let mut db = db();
+        let json = r#"{"files": [{"path": "/a", "tags": ["x"]}]}"#;
+        let summary = run(&mut db, json, Format::Json, Mode::Replace, false);
+        assert_eq!(
+            summary,
+            Summary {
+                changed: 1,
+                ..Default::default()
+            }
+        );
+        assert_eq!(state(&db)[0], file("/a", Some("a"), &["x"]));
+    }
+
+    #[test]
+    fn import_mirroring() {
+        let mut db = db();
+        let summary = run(&mut db, "/a,x\n/c,z\n", Format::Csv, Mode::Mirror, false);
+        assert_eq!(
+            summary,
+            Summary {
+                added: 1,
+                unchanged: 1,
+                removed: 1,
+                tags_added: 1,
+                tags_removed: 1,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            state(&db),
+            [
+                file("/a", Some("alias"), &["x"]),
+                file("/c", Some("c"), &["z"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn dry_run_changes_nothing() {
+        let mut db = db();
+        let before = state(&db);
+        let summary = run(&mut db, "/c,z\n", Format::Csv, Mode::Mirror, true);
+        assert_eq!(
+            summary,
+            Summary {
+                added: 1,
+                removed: 2,
+                tags_added: 1,
+                tags_removed: 2,
+                ..Default::default()
+            }
+        );
+        assert_eq!(state(&db), before);
+    }
+
+    #[test]
+    fn parse_rewrite() {
+        assert_eq!(
+            "/old=/new".parse::<Rewrite>().unwrap(),
+            Rewrite {
+                from: "/old".into(),
+                to: "/new".into(),
+            }
+        );
+        let rewrite = "/a=/b=c".parse::<Rewrite>().unwrap();
+        assert_eq!((rewrite.from, rewrite.to), ("/a".into(), "/b=c".into()));
+        for invalid in ["", "/old", "=/new", "/old=", "="] {
+            assert!(invalid.parse::<Rewrite>().is_err(), "{invalid:?}");
+        }
+    }
+
+    #[test]
+    fn rewrite_paths() {
+        let options = Options {
+            mode: Mode::Add,
+            rewrites: ["/home/old=/home/new", "/home=/mnt"]
+                .iter()
+                .map(|r| r.parse().unwrap())
+                .collect(),
+            dry_run: false,
+        };
+        let rewrite = |path: &str| options.rewrite(Path::new(path));
+        assert_eq!(rewrite("/home/old/f"), Path::new("/home/new/f"));
+        assert_eq!(rewrite("/home/other/f"), Path::new("/mnt/other/f"));
+        // Prefixes are matched by components
+        assert_eq!(rewrite("/homes/f"), Path::new("/homes/f"));
+    }
+}
@@ -1,5 +1,17 @@
+pub mod backup;
+pub mod cache;
 pub mod cli;
 pub mod config;
+pub mod daemon;
+pub mod db;
+pub mod export;
 pub mod fuse;
+pub mod import;
+pub mod pool;
+pub mod project;
+pub mod roots;
+pub mod service;
 pub mod sql;
+pub mod tmsu;
 pub mod utils;
+pub mod xattr;
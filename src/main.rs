@@ -1,37 +1,229 @@
-use anyhow::{anyhow, bail, Context, Result};
+use anyhow::{anyhow, Context, Result};
 use fuse_mt as fusemt;
 use log::{debug, info};
-use rusqlite::ParamsFromIter;
-use std::path::PathBuf;
+use std::{io::IsTerminal, path::PathBuf};
 use tg::cli::Cli;
 use tg::config::Config;
-use tg::{or_panic, sql};
+use tg::project::Project;
+
+/// How often the mount checks whether it was unmounted externally.
+const UNMOUNT_POLL: std::time::Duration = std::time::Duration::from_millis(250);
 
 #[cfg(not(target_os = "linux"))]
 const IS_LINUX: () = const { compile_error!("[tg] This crate only works on Linux due to FUSE.") };
 
 fn main() -> Result<()> {
-    let cli = tg::cli::parse().run();
+    let tg::cli::Args {
+        profile,
+        config,
+        db,
+        cli,
+    } = tg::cli::parse().run();
+    let overrides = tg::config::Overrides { config, db };
 
-    let config = tg::config::Config::load()?;
-    let db_path = config.db_path().to_path_buf();
+    // Profiles are only created by the commands that store their config
+    let config = match cli {
+        Cli::Mount { .. }
+        | Cli::Set { .. }
+        | Cli::Config {
+            config_command: tg::cli::ConfigCommand::Set { .. },
+        } => Config::create_with(profile.as_deref(), &overrides)?,
+        _ => Config::load_with(profile.as_deref(), &overrides)?,
+    };
+    // Projects are only used when no database was chosen explicitly
+    let project = match cli {
+        Cli::Add { .. } | Cli::Ls { .. } if profile.is_none() && overrides.db.is_none() => {
+            Project::find(std::env::current_dir()?)?
+        }
+        _ => None,
+    };
+    let db_path = project
+        .as_ref()
+        .map_or_else(|| config.db_path().to_path_buf(), Project::db_path);
     let mut db = rusqlite::Connection::open(&db_path).context("database creation failed")?;
-    db.execute_batch(include_str!("./sql/migrations.sql"))?;
+    tg::backup::migrate(&mut db, &db_path, config.backups())?;
 
-    let minimum_level = if matches!(cli, Cli::Mount { .. }) {
-        log::LevelFilter::Debug
-    } else {
-        log::LevelFilter::Off
+    let minimum_level = match cli {
+        Cli::Mount { .. } => log::LevelFilter::Debug,
+        Cli::Service { .. } => log::LevelFilter::Warn,
+        _ => log::LevelFilter::Off,
     };
     simple_logger::SimpleLogger::new()
         .with_level(minimum_level)
+        .with_colors(std::io::stderr().is_terminal())
         .env()
         .init()?;
 
     match cli {
-        Cli::Add { file, tags, name } => add(file, tags, &mut db)?,
-        Cli::Mount { mountpoint } => mount(mountpoint, db_path, config)?,
+        Cli::Add {
+            file,
+            tags,
+            name,
+            xattr,
+        } => add(file, tags, name, xattr, project.as_ref(), &mut db)?,
+        Cli::Ls { tags } => {
+            let roots = tg::roots::Roots::load_for(&db, &db_path)?;
+            for path in tg::db::files(&db, &tags)? {
+                match roots.resolve(&path) {
+                    Some(path) => println!("{}", path.display()),
+                    None => eprintln!("{path:?} is inside a removed root"),
+                }
+            }
+        }
+        Cli::Root { root: r } => root(r, &mut db)?,
+        Cli::Export {
+            format,
+            output,
+            tags,
+            under,
+        } => {
+            let filter = tg::export::Filter {
+                tags,
+                under: under.map(|d| d.canonicalize()).transpose()?,
+            };
+            let roots = tg::roots::Roots::load_for(&db, &db_path)?;
+            let mut out = tg::export::output(output.as_deref())?;
+            let exported = tg::export::export(&db, &roots, &filter, format, &mut out)?;
+            eprintln!("{} files exported", exported.files);
+            if exported.skipped > 0 {
+                eprintln!("{} files skipped, their root was removed", exported.skipped);
+            }
+        }
+        Cli::Import {
+            format,
+            import_flags,
+            input,
+        } => {
+            let format = format.unwrap_or(match input.as_ref().and_then(|i| i.extension()) {
+                Some(ext) if ext.eq_ignore_ascii_case("csv") => tg::export::Format::Csv,
+                _ => tg::export::Format::Json,
+            });
+            let options = import_flags.options();
+            let mut stdout = std::io::stdout().lock();
+            let diff = options
+                .dry_run
+                .then_some(&mut stdout as &mut dyn std::io::Write);
+            let reader = tg::import::input(input.as_deref())?;
+            let summary = tg::import::import(&mut db, &db_path, reader, format, &options, diff)?;
+            report_import(&options, &summary);
+        }
+        Cli::ImportTmsu {
+            import_flags,
+            database,
+        } => {
+            let tmsu_path = match database {
+                Some(path) => path,
+                None => tg::tmsu::find(&std::env::current_dir()?)
+                    .context("no TMSU database found, pass its path")?,
+            };
+            let options = import_flags.options();
+            let mut stdout = std::io::stdout().lock();
+            let diff = options
+                .dry_run
+                .then_some(&mut stdout as &mut dyn std::io::Write);
+            let report = tg::tmsu::import(&mut db, &db_path, &tmsu_path, &options, diff)?;
+            for item in &report.unconverted {
+                eprintln!("Not converted: {item}");
+            }
+            if report.implied > 0 {
+                eprintln!(
+                    "{} tags were added to files because of TMSU implications, tg does not keep them",
+                    report.implied
+                );
+            }
+            report_import(&options, &report.summary);
+        }
+        Cli::Backup { force, output } => {
+            let path = output.unwrap_or_else(|| tg::backup::path(&db_path, "tg"));
+            tg::backup::backup(&db, &path, force)?;
+            eprintln!("Backed up {db_path:?} to {path:?}");
+        }
+        Cli::Restore { backup } => {
+            if let Some(status) = tg::daemon::status(config.pid_path())? {
+                if status.db_path == db_path {
+                    anyhow::bail!(
+                        "the database is mounted to {:?} by process {}, unmount it first",
+                        status.mountpoint,
+                        status.pid
+                    );
+                }
+            }
+            let previous = tg::backup::path(&db_path, "pre-restore");
+            tg::backup::restore(&mut db, &backup, Some(&previous))?;
+            eprintln!("Restored {backup:?}, the previous database was saved to {previous:?}");
+        }
+        Cli::Config { config_command } => config_cmd(config_command, config)?,
+        Cli::Init { dir } => {
+            let project = Project::init(dir.unwrap_or(std::env::current_dir()?))?;
+            let mut db = rusqlite::Connection::open(project.db_path())
+                .context("database creation failed")?;
+            tg::sql::migrate(&mut db)?;
+            eprintln!("Created project database {:?}", project.db_path());
+        }
+        Cli::ImportXattrs { dir } => {
+            let report = tg::xattr::import(&mut db, &db_path, &dir)?;
+            report_xattrs(&report);
+        }
+        Cli::ExportXattrs { dir } => {
+            let report = tg::xattr::export(&db, &db_path, &dir)?;
+            report_xattrs(&report);
+        }
+        Cli::Mount {
+            daemon,
+            all,
+            mount_flags,
+            mountpoint,
+        } => {
+            let configs = if all {
+                if mountpoint.is_some() {
+                    anyhow::bail!("MOUNTPOINT cannot be used with --all");
+                }
+                if !overrides.is_empty() {
+                    anyhow::bail!("--config and --db cannot be used with --all");
+                }
+                let configs = Config::load_all()?
+                    .into_iter()
+                    .filter(|c| c.mountpoint().is_some())
+                    .collect::<Vec<_>>();
+                for c in &configs {
+                    let mut db = rusqlite::Connection::open(c.db_path())?;
+                    tg::backup::migrate(&mut db, c.db_path(), c.backups())?;
+                }
+                configs
+            } else {
+                vec![config]
+            };
+            let Some(first) = configs.first() else {
+                anyhow::bail!(
+                    "no profile has a mountpoint, set it with 'tg --profile NAME mount MOUNTPOINT'"
+                );
+            };
+            if daemon {
+                let status = tg::daemon::spawn(first.pid_path(), first.log_path())?;
+                eprintln!(
+                    "Mounted to {:?} in the background (pid {}), logging to {:?}",
+                    status.mountpoint,
+                    status.pid,
+                    first.log_path()
+                );
+            } else {
+                mount(mountpoint, configs, &mount_flags)?
+            }
+        }
+        Cli::Unmount { mountpoint } => unmount(mountpoint, &config)?,
+        Cli::Status => status(&config)?,
+        Cli::Profiles => {
+            for c in Config::load_all()? {
+                let mountpoint = c.mountpoint().map(|m| m.display().to_string());
+                println!(
+                    "{}\t{}",
+                    c.profile().unwrap_or("default"),
+                    mountpoint.as_deref().unwrap_or("-")
+                );
+            }
+        }
         Cli::Set { set: s } => set(s, config)?,
+        Cli::Service { service: s } => service(s, &config, &overrides)?,
     }
 
     Ok(())
@@ -40,121 +232,294 @@ fn main() -> Result<()> {
 fn add(
     file: impl AsRef<std::path::Path>,
     tags: impl AsRef<[String]>,
+    name: Option<String>,
+    xattr: bool,
+    project: Option<&Project>,
     db: &mut rusqlite::Connection,
 ) -> Result<()> {
-    let file = file.as_ref();
-
-    if let Some(name) = file.file_name() {
-        let name = name.to_string_lossy();
-        if name.starts_with(sql::DUP_SEP) {
-            bail!("invalid name {name:?}, files cannot start with '{}'", sql::DUP_SEP);
-        }
-        if let Some(sql::DUP_SEP) = name.chars().find(|n| !n.is_ascii_digit()) {
-            bail!("invalid name {name:?}, files cannot start with a number and '{}'", sql::DUP_SEP);
-        }
-    }
-    
-    let path = file.canonicalize().with_context(|| format!("the file {file:?} could not be found"))?;
     let tags = tags.as_ref();
-
-    debug!("Adding {path:?} : {tags:?}");
-
-    let tx = db.transaction()?;
-    {
-        let path = path.to_string_lossy();
-        tx.execute(sql::INSERT_FILE, [path.as_ref()])
-            .context("could not insert file")?;
-        let mut insert_tag_stmt = tx.prepare_cached(sql::INSERT_TAG)?;
-        let mut insert_filetag_stmt = tx.prepare_cached(sql::INSERT_FILETAG)?;
-        for tag in tags {
-            insert_tag_stmt
-                .execute([tag])
-                .context("could not insert tag")?;
-            insert_filetag_stmt
-                .execute([path.as_ref(), tag.as_str()])
-                .context("could not insert filetag")?;
-        }
+    let path = match project {
+        Some(project) => tg::db::add_relative(db, project.root(), file, tags, name.as_deref())?,
+        None => tg::db::add(db, file, tags, name.as_deref())?,
+    };
+    if xattr {
+        tg::xattr::add_tags(&path, tags)
+            .with_context(|| format!("could not write the extended attributes of {path:?}"))?;
     }
-    tx.commit()?;
 
     eprintln!("{tags:?} added to {path:?}");
 
     Ok(())
 }
 
-/// Mounts the virtual filesystem.
+fn report_import(options: &tg::import::Options, summary: &tg::import::Summary) {
+    if options.dry_run {
+        eprintln!("Dry run, nothing was changed: {summary}");
+    } else {
+        eprintln!("{summary}");
+    }
+}
+
+fn report_xattrs(report: &tg::xattr::Report) {
+    for s in &report.skipped {
+        eprintln!("{:?} skipped: {:#}", s.path, s.error);
+    }
+    let conflicts = &report.conflicts;
+    for c in conflicts {
+        if !c.db_only.is_empty() {
+            eprintln!("{:?}: {:?} only in the database", c.path, c.db_only);
+        }
+        if !c.xattr_only.is_empty() {
+            eprintln!(
+                "{:?}: {:?} only in '{}'",
+                c.path,
+                c.xattr_only,
+                tg::xattr::XDG_TAGS
+            );
+        }
+    }
+    eprintln!(
+        "{} conflicts found, {} skipped",
+        conflicts.len(),
+        report.skipped.len()
+    );
+}
+
+/// Mounts the virtual filesystem of every config, `mountpoint` overrides the stored one
+/// and `flags` the stored mount options, without being stored.
 ///
-/// Blocks until unmounted or interrupted.
+/// Blocks until all are unmounted or interrupted.
 fn mount(
     mountpoint: Option<PathBuf>,
-    db_path: impl Into<std::path::PathBuf>,
-    mut config: Config,
+    configs: Vec<Config>,
+    flags: &tg::cli::MountFlags,
 ) -> Result<()> {
     use std::sync::{Arc, Condvar, Mutex};
 
-    let mountpoint = match (mountpoint, config.mountpoint()) {
-        (Some(mountpoint), _) => {
-            if !mountpoint.try_exists()? {
-                return Err(anyhow!("the directory {mountpoint:?} does not exist"));
+    let mut sessions = Vec::new();
+    for mut config in configs {
+        let name = config.profile().unwrap_or("default").to_string();
+        let mountpoint = match (&mountpoint, config.mountpoint()) {
+            (Some(mountpoint), _) => {
+                if !mountpoint.try_exists()? {
+                    return Err(anyhow!("the directory {mountpoint:?} does not exist"));
+                }
+                config.set_mountpoint(mountpoint.clone())?;
+                mountpoint.clone()
             }
-            config.set_mountpoint(mountpoint.clone())?;
-            mountpoint
-        }
-        (None, Some(mountpoint)) => mountpoint.to_path_buf(),
-        (None, None) => {
-            anyhow::bail!("no default mountpoint found, set it with 'tg mount MOUNTPOINT'")
-        }
-    };
+            (None, Some(mountpoint)) => mountpoint.to_path_buf(),
+            (None, None) => {
+                anyhow::bail!("no default mountpoint found, set it with 'tg mount MOUNTPOINT'")
+            }
+        };
+
+        config
+            .prefixes()
+            .validate()
+            .with_context(|| format!("invalid prefixes in {:?}", config.config_path()))?;
+        // Applied after storing the mountpoint so the flags only affect this mount
+        flags.apply(config.mount_options_mut());
+        config
+            .mount_options()
+            .validate()
+            .with_context(|| format!("invalid mount options of {name}"))?;
+
+        let db_path = config.db_path().to_path_buf();
+        let pid_file = tg::daemon::PidFile::create(
+            config.pid_path(),
+            &tg::daemon::Status {
+                pid: std::process::id(),
+                mountpoint: mountpoint.canonicalize()?,
+                db_path: db_path.clone(),
+            },
+        )?;
 
-    let handle = fusemt::spawn_mount(
-        fusemt::FuseMT::new(
-            tg::fuse::Fuse::new(db_path, config),
-            std::thread::available_parallelism()?.get(),
-        ),
-        &mountpoint,
-        &[],
-    )?;
-    info!("Mounted successfully to {mountpoint:?}");
-
-    let unmounted = Arc::new((Mutex::new(false), Condvar::new()));
-    let killed = Arc::clone(&unmounted);
+        let options = config.mount_options().kernel_options();
+        debug!("Mount options of {name}: {options:?}");
+        let options = options.iter().map(std::ffi::OsStr::new).collect::<Vec<_>>();
+        let handle = fusemt::spawn_mount(
+            fusemt::FuseMT::new(
+                tg::fuse::Fuse::new(db_path, config),
+                std::thread::available_parallelism()?.get(),
+            ),
+            &mountpoint,
+            &options,
+        )?;
+        info!("Mounted {name} successfully to {mountpoint:?}");
+        // Fields are dropped in declaration order, unmounting before releasing the PID file
+        sessions.push((name, handle, pid_file));
+    }
+
+    let killed = Arc::new((Mutex::new(false), Condvar::new()));
+    let handler = Arc::clone(&killed);
     ctrlc::set_handler(move || {
-        let (lock, cvar) = &*killed;
+        let (lock, cvar) = &*handler;
         let mut killed = lock.lock().unwrap();
         *killed = true;
         cvar.notify_one();
         debug!(target: "unmount", "Process killed via signal");
     })?;
-    let unmounted_extern = Arc::clone(&unmounted);
-    std::thread::spawn(move || {
-        // Ignore result of join(), allowing for system to be unmounted
-        let _ = handle.guard.join();
-
-        let (lock, cvar) = &*unmounted_extern;
-        let mut unmounted = lock.lock().unwrap();
-        if !*unmounted {
-            *unmounted = true;
-            cvar.notify_one();
-            debug!("[tg::unmounted] Unmounted manually");
-        }
-    });
-
-    let (lock, cvar) = &*unmounted;
-    let mut unmounted = lock.lock().unwrap();
-    while !*unmounted {
-        unmounted = cvar.wait(unmounted).unwrap();
+
+    let (lock, cvar) = &*killed;
+    let mut killed = lock.lock().unwrap();
+    while !*killed && !sessions.is_empty() {
+        killed = cvar.wait_timeout(killed, UNMOUNT_POLL).unwrap().0;
+        sessions.retain(|(name, handle, _)| {
+            let unmounted = handle.guard.is_finished();
+            if unmounted {
+                debug!("[tg::unmounted] {name} unmounted manually");
+            }
+            !unmounted
+        });
     }
+    drop(sessions);
     info!("Filesystem unmounted");
 
     Ok(())
 }
 
+/// Unmounts `mountpoint`, or the running mount if skipped, and waits for its process to exit.
+fn unmount(mountpoint: Option<PathBuf>, config: &Config) -> Result<()> {
+    let running = tg::daemon::status(config.pid_path())?;
+    let mountpoint = match (mountpoint, &running) {
+        (Some(mountpoint), _) => mountpoint.canonicalize()?,
+        (None, Some(running)) => running.mountpoint.clone(),
+        (None, None) => match config.mountpoint() {
+            Some(mountpoint) => mountpoint.to_path_buf(),
+            None => anyhow::bail!("the filesystem is not mounted"),
+        },
+    };
+    tg::daemon::unmount(&mountpoint)?;
+    if running.is_some_and(|r| r.mountpoint == mountpoint) {
+        tg::daemon::wait_exit(config.pid_path())?;
+    }
+    eprintln!("Unmounted {mountpoint:?}");
+    Ok(())
+}
+
+fn status(config: &Config) -> Result<()> {
+    let Some(status) = tg::daemon::status(config.pid_path())? else {
+        println!("Not mounted");
+        return Ok(());
+    };
+    if tg::daemon::is_mounted(&status.mountpoint)? {
+        println!(
+            "Mounted to {:?} by process {}",
+            status.mountpoint, status.pid
+        );
+    } else {
+        println!(
+            "Process {} is running but {:?} is not mounted",
+            status.pid, status.mountpoint
+        );
+    }
+    println!("Database: {:?}", status.db_path);
+    Ok(())
+}
+
+fn root(command: tg::cli::Root, db: &mut rusqlite::Connection) -> Result<()> {
+    match command {
+        tg::cli::Root::Add { name, dir } => {
+            let moved = tg::roots::add(db, &name, &dir)?;
+            eprintln!("Root {name:?} added, {moved} files stored relative to it");
+        }
+        tg::cli::Root::Set { name, dir } => {
+            tg::roots::set(db, &name, &dir)?;
+            eprintln!("Root {name:?} set to {dir:?}");
+        }
+        tg::cli::Root::Rm { name } => {
+            let moved = tg::roots::remove(db, &name)?;
+            eprintln!("Root {name:?} removed, {moved} files stored with absolute paths");
+        }
+        tg::cli::Root::Ls => {
+            for root in tg::roots::Roots::load(db)?.iter() {
+                println!("{}\t{}", root.name, root.path.display());
+            }
+        }
+    }
+    Ok(())
+}
+
+fn service(
+    command: tg::cli::Service,
+    config: &Config,
+    overrides: &tg::config::Overrides,
+) -> Result<()> {
+    let profile = config.profile();
+    let unit_name = tg::service::unit_name(profile);
+
+    match command {
+        tg::cli::Service::Install { no_enable } => {
+            let Some(mountpoint) = config.mountpoint() else {
+                anyhow::bail!("no default mountpoint found, set it with 'tg mount MOUNTPOINT'");
+            };
+            let path = tg::service::install(profile, overrides, mountpoint, !no_enable)?;
+            eprintln!("{unit_name} written to {path:?}");
+            if no_enable {
+                eprintln!("Start it with 'systemctl --user enable --now {unit_name}'");
+            }
+        }
+        tg::cli::Service::Uninstall => {
+            tg::service::uninstall(profile)?;
+            eprintln!("{unit_name} removed");
+        }
+        tg::cli::Service::Status => {
+            let path = tg::service::unit_path(profile);
+            if !path.try_exists()? {
+                println!("{unit_name} is not installed");
+                return Ok(());
+            }
+            let active = tg::service::systemctl(&["is-active", &unit_name])
+                .unwrap_or_else(|_| String::from("inactive"));
+            println!("{unit_name} installed at {path:?} ({active})");
+        }
+    }
+    Ok(())
+}
+
 fn set(key: tg::cli::Set, mut config: Config) -> Result<()> {
     let msg = format!("{key:?} set successfully");
+    let prefixes = config.prefixes();
     match key {
         tg::cli::Set::TagPrefix { value } => config.set_tag_prefix(value)?,
         tg::cli::Set::FilePrefix { value } => config.set_file_prefix(value)?,
+        tg::cli::Set::Inbox { value } => config.set_inbox(value)?,
     }
     info!("{msg}");
+    report_prefix_change(&config, &prefixes)
+}
+
+fn config_cmd(command: tg::cli::ConfigCommand, mut config: Config) -> Result<()> {
+    use tg::cli::ConfigCommand;
+
+    let prefixes = config.prefixes();
+    match command {
+        ConfigCommand::Show => print!("{}", config.to_toml()?),
+        ConfigCommand::Get { key } => match config.get(&key)? {
+            Some(value) => println!("{value}"),
+            None => anyhow::bail!("{key} is not set"),
+        },
+        ConfigCommand::Set { key, value } => config.set(&key, &value)?,
+        ConfigCommand::Path => println!("{}", config.config_path().display()),
+        ConfigCommand::Reset { key } => config.reset(key.as_deref())?,
+        ConfigCommand::Keys => {
+            for (key, kind) in tg::config::KEYS {
+                println!("{key}\t{}", format!("{kind:?}").to_lowercase());
+            }
+        }
+    }
+    report_prefix_change(&config, &prefixes)
+}
+
+/// Tells the user that the running mount switched away from the `old` prefixes.
+fn report_prefix_change(config: &Config, old: &tg::config::Prefixes) -> Result<()> {
+    if config.prefixes() != *old {
+        if let Some(running) = tg::daemon::status(config.pid_path())? {
+            eprintln!(
+                "The mount at {:?} now uses the new prefixes, paths with the old ones no longer resolve",
+                running.mountpoint
+            );
+        }
+    }
     Ok(())
 }
@@ -0,0 +1,94 @@
+use std::{
+    ops::{Deref, DerefMut},
+    path::PathBuf,
+    sync::{Mutex, MutexGuard},
+};
+
+use rusqlite::{Connection, OpenFlags};
+
+/// SQLite connections reused across operations, keeping their cached statements.
+///
+/// Read-only connections are opened on demand and returned to the pool when dropped,
+/// writes are serialized through a single connection.
+pub struct Pool {
+    path: PathBuf,
+    readers: Mutex<Vec<Connection>>,
+    writer: Mutex<Option<Connection>>,
+}
+
+impl Pool {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Pool {
+            path: path.into(),
+            readers: Mutex::default(),
+            writer: Mutex::default(),
+        }
+    }
+
+    /// Takes a read-only connection from the pool, opening a new one if all are in use.
+    pub fn read(&self) -> rusqlite::Result<ReadConnection<'_>> {
+        let conn = self.readers.lock().unwrap().pop();
+        let conn = match conn {
+            Some(conn) => conn,
+            None => Connection::open_with_flags(
+                &self.path,
+                OpenFlags::SQLITE_OPEN_NO_MUTEX | OpenFlags::SQLITE_OPEN_READ_ONLY,
+            )?,
+        };
+        Ok(ReadConnection {
+            pool: self,
+            conn: Some(conn),
+        })
+    }
+
+    /// Locks the writer connection, blocking until other writes finish.
+    pub fn write(&self) -> rusqlite::Result<WriteConnection<'_>> {
+        let mut writer = self.writer.lock().unwrap();
+        if writer.is_none() {
+            *writer = Some(Connection::open_with_flags(
+                &self.path,
+                OpenFlags::SQLITE_OPEN_NO_MUTEX | OpenFlags::SQLITE_OPEN_READ_WRITE,
+            )?);
+        }
+        Ok(WriteConnection(writer))
+    }
+}
+
+/// Read-only connection, returned to its [`Pool`] when dropped.
+pub struct ReadConnection<'a> {
+    pool: &'a Pool,
+    conn: Option<Connection>,
+}
+
+impl Deref for ReadConnection<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.conn.as_ref().unwrap()
+    }
+}
+
+impl Drop for ReadConnection<'_> {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            self.pool.readers.lock().unwrap().push(conn);
+        }
+    }
+}
+
+/// Exclusive access to the writer connection of a [`Pool`].
+pub struct WriteConnection<'a>(MutexGuard<'a, Option<Connection>>);
+
+impl Deref for WriteConnection<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.0.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for WriteConnection<'_> {
+    fn deref_mut(&mut self) -> &mut Connection {
+        self.0.as_mut().unwrap()
+    }
+}
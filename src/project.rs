@@ -0,0 +1,61 @@
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+/// Directory marking the root of a project, like `.git`.
+pub const DIR: &str = ".tg";
+
+/// A directory tree with its own database in `ROOT/.tg/db.sqlite`.
+///
+/// Paths are stored relative to the root, so the database stays valid when the tree is moved.
+#[derive(Debug, Clone)]
+pub struct Project {
+    root: PathBuf,
+}
+
+impl Project {
+    /// Finds the closest project containing `start`, checking its ancestors up to `/`.
+    pub fn find(start: impl AsRef<Path>) -> Result<Option<Self>> {
+        let start = start
+            .as_ref()
+            .canonicalize()
+            .with_context(|| format!("could not find {:?}", start.as_ref()))?;
+        for dir in start.ancestors() {
+            if dir.join(DIR).is_dir() {
+                return Ok(Some(Project {
+                    root: dir.to_path_buf(),
+                }));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Creates a project rooted at `root`, doing nothing if it already exists.
+    pub fn init(root: impl AsRef<Path>) -> Result<Self> {
+        let root = root
+            .as_ref()
+            .canonicalize()
+            .with_context(|| format!("could not find {:?}", root.as_ref()))?;
+        let dir = root.join(DIR);
+        std::fs::create_dir_all(&dir).with_context(|| format!("could not create {dir:?}"))?;
+        Ok(Project { root })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn db_path(&self) -> PathBuf {
+        self.root.join(DIR).join("db.sqlite")
+    }
+
+    /// Returns the project owning the database at `db_path`, if it's inside a `.tg` directory.
+    pub fn from_db_path(db_path: impl AsRef<Path>) -> Option<Self> {
+        let dir = db_path.as_ref().parent()?;
+        if dir.file_name()? != DIR {
+            return None;
+        }
+        Some(Project {
+            root: dir.parent()?.to_path_buf(),
+        })
+    }
+}
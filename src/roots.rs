@@ -0,0 +1,311 @@
+use anyhow::{bail, Context, Result};
+use log::debug;
+use std::path::{Path, PathBuf};
+
+use crate::sql;
+
+/// Starts the stored path of a file relative to a root, as in `~ID/relative/path`.
+pub const MARKER: char = '~';
+
+/// A directory that file paths are stored relative to, so it can be moved with `tg root set`.
+#[derive(Debug, Clone)]
+pub struct Root {
+    pub id: i64,
+    pub name: String,
+    pub path: PathBuf,
+}
+
+/// Converts between the absolute path of a file and the path stored in the database.
+///
+/// Stored paths are either absolute, relative to a root (`~ID/relative`)
+/// or, in project databases, relative to the project root.
+#[derive(Debug, Clone, Default)]
+pub struct Roots {
+    roots: Vec<Root>,
+    base: Option<PathBuf>,
+}
+
+impl Roots {
+    pub fn load(db: &rusqlite::Connection) -> rusqlite::Result<Self> {
+        let mut stmt = db.prepare_cached(sql::GET_ROOTS)?;
+        let roots = stmt.query_map([], |r| {
+            Ok(Root {
+                id: r.get(0)?,
+                name: r.get(1)?,
+                path: r.get::<_, String>(2)?.into(),
+            })
+        })?;
+        Ok(Roots {
+            roots: roots.collect::<rusqlite::Result<_>>()?,
+            base: None,
+        })
+    }
+
+    /// Loads the roots of the database at `db_path`, resolving relative paths
+    /// against the project root if it's a project database.
+    pub fn load_for(db: &rusqlite::Connection, db_path: &Path) -> rusqlite::Result<Self> {
+        let roots = Roots::load(db)?;
+        Ok(match crate::project::Project::from_db_path(db_path) {
+            Some(project) => roots.with_base(project.root()),
+            None => roots,
+        })
+    }
+
+    /// Resolves paths without a root relative to `base`, used by project databases.
+    pub fn with_base(self, base: impl Into<PathBuf>) -> Self {
+        Roots {
+            base: Some(base.into()),
+            ..self
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Root> {
+        self.roots.iter()
+    }
+
+    /// Stored form of the absolute `path`, relative to the deepest root containing it
+    /// or to the project root.
+    pub fn store(&self, path: &Path) -> String {
+        let root = self
+            .roots
+            .iter()
+            .filter_map(|r| Some((r, path.strip_prefix(&r.path).ok()?)))
+            .filter(|(_, relative)| !relative.as_os_str().is_empty())
+            .max_by_key(|(r, _)| r.path.components().count());
+        match root {
+            Some((root, relative)) => {
+                format!("{MARKER}{}/{}", root.id, relative.to_string_lossy())
+            }
+            None => match self.base.as_ref().and_then(|b| path.strip_prefix(b).ok()) {
+                Some(relative) if !relative.as_os_str().is_empty() => {
+                    relative.to_string_lossy().into_owned()
+                }
+                _ => path.to_string_lossy().into_owned(),
+            },
+        }
+    }
+
+    /// Current absolute path of the `stored` one, `None` if its root was removed.
+    pub fn resolve(&self, stored: &str) -> Option<PathBuf> {
+        if let Some((id, relative)) = stored.strip_prefix(MARKER).and_then(|s| s.split_once('/')) {
+            if let Ok(id) = id.parse::<i64>() {
+                let root = self.roots.iter().find(|r| r.id == id)?;
+                return Some(root.path.join(relative));
+            }
+        }
+        match &self.base {
+            Some(base) => Some(base.join(stored)),
+            None => Some(stored.into()),
+        }
+    }
+}
+
+/// Stored form of the absolute `path` using the roots of `db`.
+pub fn store(db: &rusqlite::Connection, path: &Path) -> rusqlite::Result<String> {
+    Ok(Roots::load(db)?.store(path))
+}
+
+/// Rewrites the stored path of every file after the roots changed from `old` to `new`.
+fn rebase(tx: &rusqlite::Transaction, old: &Roots, new: &Roots) -> Result<usize> {
+    let paths = tx
+        .prepare(sql::ALL_FILES)?
+        .query_map([], |r| r.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let mut stmt = tx.prepare(sql::SET_FILE_PATH)?;
+    let mut moved = 0;
+    for path in paths {
+        let Some(absolute) = old.resolve(&path) else {
+            continue;
+        };
+        let rebased = new.store(&absolute);
+        if rebased != path {
+            debug!("Rebasing {path:?} to {rebased:?}");
+            stmt.execute([&path, &rebased])
+                .with_context(|| format!("could not move {path:?} to {rebased:?}"))?;
+            moved += 1;
+        }
+    }
+    Ok(moved)
+}
+
+fn canonical_dir(path: &Path) -> Result<PathBuf> {
+    let path = path
+        .canonicalize()
+        .with_context(|| format!("the directory {path:?} could not be found"))?;
+    if !path.is_dir() {
+        bail!("{path:?} is not a directory");
+    }
+    Ok(path)
+}
+
+/// Adds the root `name` at `path`, storing the files inside it relative to it.
+///
+/// Returns the number of files moved to the new root.
+pub fn add(db: &mut rusqlite::Connection, name: &str, path: &Path) -> Result<usize> {
+    if name.is_empty() || name.contains('/') {
+        bail!("invalid root name {name:?}, it cannot be empty or contain '/'");
+    }
+    let path = canonical_dir(path)?;
+    let tx = db.transaction()?;
+    let old = Roots::load(&tx)?;
+    tx.execute(sql::INSERT_ROOT, [name, &path.to_string_lossy()])
+        .with_context(|| format!("could not add the root {name:?}"))?;
+    let moved = rebase(&tx, &old, &Roots::load(&tx)?)?;
+    tx.commit()?;
+    Ok(moved)
+}
+
+/// Points the root `name` to `path`, where its files are now found.
+pub fn set(db: &rusqlite::Connection, name: &str, path: &Path) -> Result<()> {
+    let path = canonical_dir(path)?;
+    if db.execute(sql::SET_ROOT_PATH, [name, &path.to_string_lossy()])? == 0 {
+        bail!("the root {name:?} does not exist");
+    }
+    Ok(())
+}
+
+/// Removes the root `name`, storing the absolute path of its files.
+///
+/// Returns the number of files moved out of the root.
+pub fn remove(db: &mut rusqlite::Connection, name: &str) -> Result<usize> {
+    let tx = db.transaction()?;
+    let old = Roots::load(&tx)?;
+    if !old.iter().any(|r| r.name == name) {
+        bail!("the root {name:?} does not exist");
+    }
+    tx.execute(sql::DELETE_ROOT, [name])?;
+    let moved = rebase(&tx, &old, &Roots::load(&tx)?)?;
+    tx.commit()?;
+    Ok(moved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roots(roots: &[(i64, &str)]) -> Roots {
+        Roots {
+            roots: roots
+                .iter()
+                .map(|&(id, path)| Root {
+                    id,
+                    name: format!("r{id}"),
+                    path: path.into(),
+                })
+                .collect(),
+            base: None,
+        }
+    }
+
+    fn store(roots: &Roots, path: &str) -> String {
+        roots.store(Path::new(path))
+    }
+
+    #[test]
+    fn store_without_roots() {
+        assert_eq!(store(&Roots::default(), "/mnt/a/f"), "/mnt/a/f");
+    }
+
+    #[test]
+    fn store_in_deepest_root() {
+        let roots = roots(&[(1, "/mnt"), (2, "/mnt/a"), (3, "/mnt/a/b/c")]);
+        assert_eq!(store(&roots, "/mnt/a/b/f"), "~2/b/f");
+        assert_eq!(store(&roots, "/mnt/x/f"), "~1/x/f");
+        assert_eq!(store(&roots, "/mnt/a/b/c/f"), "~3/f");
+        // A root isn't stored relative to itself
+        assert_eq!(store(&roots, "/mnt/a"), "~1/a");
+        assert_eq!(store(&roots, "/mnt"), "/mnt");
+    }
+
+    #[test]
+    fn store_outside_string_prefix_root() {
+        let roots = roots(&[(1, "/mnt/a")]);
+        assert_eq!(store(&roots, "/mnt/ab/f"), "/mnt/ab/f");
+        assert_eq!(store(&roots, "/mnt/a/b/f"), "~1/b/f");
+    }
+
+    #[test]
+    fn store_relative_to_base() {
+        let project = Roots::default().with_base("/home/me/project");
+        assert_eq!(store(&project, "/home/me/project/src/f"), "src/f");
+        assert_eq!(
+            store(&project, "/home/me/projects/f"),
+            "/home/me/projects/f"
+        );
+        assert_eq!(store(&project, "/home/me/project"), "/home/me/project");
+
+        // Roots take precedence over the base
+        let roots = roots(&[(1, "/home/me/project/src")]).with_base("/home/me/project");
+        assert_eq!(store(&roots, "/home/me/project/src/f"), "~1/f");
+        assert_eq!(store(&roots, "/home/me/project/doc/f"), "doc/f");
+    }
+
+    #[test]
+    fn resolve() {
+        let roots = roots(&[(1, "/mnt"), (2, "/media/usb")]);
+        assert_eq!(roots.resolve("~2/b/f"), Some("/media/usb/b/f".into()));
+        assert_eq!(roots.resolve("/abs/f"), Some("/abs/f".into()));
+        assert_eq!(roots.resolve("~3/f"), None);
+        // Only `~ID/` is a marker
+        assert_eq!(roots.resolve("~name/f"), Some("~name/f".into()));
+
+        let project = roots.with_base("/project");
+        assert_eq!(project.resolve("src/f"), Some("/project/src/f".into()));
+        assert_eq!(project.resolve("/abs/f"), Some("/abs/f".into()));
+        assert_eq!(project.resolve("~1/f"), Some("/mnt/f".into()));
+    }
+
+    #[test]
+    fn store_resolve_round_trip() {
+        let roots = roots(&[(1, "/mnt"), (2, "/mnt/a")]).with_base("/project");
+        for path in [
+            "/mnt/a/b/f",
+            "/mnt/f",
+            "/project/src/f",
+            "/other/f",
+            "/mnt/a",
+        ] {
+            let stored = roots.store(Path::new(path));
+            assert_eq!(roots.resolve(&stored), Some(path.into()), "{stored}");
+        }
+    }
+
+    #[test]
+    fn rebase_on_add_and_remove() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("tg-roots-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("r").join("sub"))?;
+        let dir = dir.canonicalize()?;
+        let inside = dir.join("r").join("sub").join("f");
+        let prefix = dir.join("rx").join("f");
+
+        let mut db = rusqlite::Connection::open_in_memory()?;
+        sql::migrate(&mut db)?;
+        for path in [&inside, &prefix] {
+            db.execute(sql::INSERT_FILE, [path.to_string_lossy()])?;
+        }
+        let paths = |db: &rusqlite::Connection| -> Result<Vec<String>> {
+            let mut stmt = db.prepare(sql::ALL_FILES)?;
+            let paths = stmt.query_map([], |r| r.get(0))?;
+            Ok(paths.collect::<rusqlite::Result<_>>()?)
+        };
+
+        let added = add(&mut db, "r", &dir.join("r"));
+        let stored = paths(&db);
+        std::fs::remove_dir_all(&dir)?;
+        assert_eq!(added?, 1);
+        let id = Roots::load(&db)?.iter().next().unwrap().id;
+        let mut stored = stored?;
+        stored.sort();
+        let mut expected = vec![format!("~{id}/sub/f"), prefix.to_string_lossy().into()];
+        expected.sort();
+        assert_eq!(stored, expected);
+
+        assert_eq!(remove(&mut db, "r")?, 1);
+        let mut stored = paths(&db)?;
+        stored.sort();
+        let mut expected = vec![inside.to_string_lossy(), prefix.to_string_lossy()];
+        expected.sort();
+        assert_eq!(stored, expected);
+        Ok(())
+    }
+}
@@ -0,0 +1,201 @@
+use anyhow::{bail, Context, Result};
+use log::warn;
+
+use crate::config::Overrides;
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Name of the systemd user unit that mounts the filesystem of `profile`.
+///
+/// `tg.service` for the default profile, `tg-NAME.service` for the others.
+pub fn unit_name(profile: Option<&str>) -> String {
+    match profile {
+        Some(name) => format!("tg-{name}.service"),
+        None => String::from("tg.service"),
+    }
+}
+
+/// Path of the unit file, `~/.config/systemd/user/UNIT_NAME`.
+pub fn unit_path(profile: Option<&str>) -> PathBuf {
+    let dirs = crate::or_panic!(
+        directories::BaseDirs::new(),
+        "could not get the home directory"
+    );
+    dirs.config_dir()
+        .join("systemd")
+        .join("user")
+        .join(unit_name(profile))
+}
+
+/// Quotes `arg` so systemd passes it unchanged as a single argument.
+fn quote(arg: &Path) -> String {
+    let arg = arg
+        .to_string_lossy()
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('%', "%%")
+        .replace('$', "$$");
+    format!("\"{arg}\"")
+}
+
+/// Contents of the unit running `exe [--profile NAME] [--config PATH] [--db PATH] mount mountpoint`.
+///
+/// systemd stops the service with SIGTERM, which unmounts through the termination handler of `tg mount`.
+pub fn unit(exe: &Path, profile: Option<&str>, overrides: &Overrides, mountpoint: &Path) -> String {
+    let flags = [
+        ("--profile", profile.map(Path::new)),
+        ("--config", overrides.config.as_deref()),
+        ("--db", overrides.db.as_deref()),
+    ];
+    let flags = flags
+        .into_iter()
+        .filter_map(|(flag, value)| Some(format!(" {flag} {}", quote(value?))))
+        .collect::<String>();
+    format!(
+        "\
+[Unit]
+Description=tg tag filesystem mounted to {description}
+Documentation=https://github.com/LyonSyonII/tg
+
+[Service]
+Type=simple
+ExecStart={exe}{flags} mount {mountpoint}
+Restart=on-failure
+
+[Install]
+WantedBy=default.target
+",
+        description = mountpoint.to_string_lossy().replace('%', "%%"),
+        exe = quote(exe),
+        mountpoint = quote(mountpoint),
+    )
+}
+
+/// Runs `systemctl --user args`, returning its standard output.
+pub fn systemctl(args: &[&str]) -> Result<String> {
+    let output = Command::new("systemctl")
+        .arg("--user")
+        .args(args)
+        .output()
+        .context("could not run systemctl")?;
+    if !output.status.success() {
+        bail!(
+            "'systemctl --user {}' failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Writes the unit mounting `profile` to `mountpoint`, enabling and starting it if `enable` is set.
+///
+/// Returns the path of the unit file.
+pub fn install(
+    profile: Option<&str>,
+    overrides: &Overrides,
+    mountpoint: &Path,
+    enable: bool,
+) -> Result<PathBuf> {
+    let overrides = Overrides {
+        config: overrides
+            .config
+            .as_deref()
+            .map(std::path::absolute)
+            .transpose()?,
+        db: overrides
+            .db
+            .as_deref()
+            .map(std::path::absolute)
+            .transpose()?,
+    };
+    let exe = std::env::current_exe()?;
+    let mountpoint = mountpoint
+        .canonicalize()
+        .with_context(|| format!("the directory {mountpoint:?} does not exist"))?;
+    let path = unit_path(profile);
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(&path, unit(&exe, profile, &overrides, &mountpoint))
+        .with_context(|| format!("could not write {path:?}"))?;
+
+    if enable {
+        systemctl(&["daemon-reload"])?;
+        systemctl(&["enable", "--now", &unit_name(profile)])?;
+    }
+    Ok(path)
+}
+
+/// Stops and disables the unit, then removes its file.
+pub fn uninstall(profile: Option<&str>) -> Result<()> {
+    let (name, path) = (unit_name(profile), unit_path(profile));
+    if !path.try_exists()? {
+        bail!("{name} is not installed");
+    }
+    if let Err(e) = systemctl(&["disable", "--now", &name]) {
+        warn!("could not disable {name}: {e}");
+    }
+    std::fs::remove_file(&path).with_context(|| format!("could not remove {path:?}"))?;
+    if let Err(e) = systemctl(&["daemon-reload"]) {
+        warn!("could not reload systemd: {e}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exec_start(unit: &str) -> &str {
+        unit.lines()
+            .find_map(|l| l.strip_prefix("ExecStart="))
+            .expect("no ExecStart line")
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(unit_name(None), "tg.service");
+        assert_eq!(unit_name(Some("work")), "tg-work.service");
+    }
+
+    #[test]
+    fn quotes() {
+        assert_eq!(quote(Path::new("/mnt/tags")), r#""/mnt/tags""#);
+        assert_eq!(quote(Path::new("/my tags")), r#""/my tags""#);
+        assert_eq!(quote(Path::new(r#"/a"b\c%d$e"#)), r#""/a\"b\\c%%d$$e""#);
+    }
+
+    #[test]
+    fn exec_start_default() {
+        let unit = unit(
+            Path::new("/usr/bin/tg"),
+            None,
+            &Overrides::default(),
+            Path::new("/mnt/tags"),
+        );
+        assert_eq!(exec_start(&unit), r#""/usr/bin/tg" mount "/mnt/tags""#);
+        assert!(unit.contains("Description=tg tag filesystem mounted to /mnt/tags\n"));
+    }
+
+    #[test]
+    fn exec_start_flags() {
+        let overrides = Overrides {
+            config: Some(PathBuf::from("/home/me/my config.toml")),
+            db: Some(PathBuf::from("/home/me/db.sqlite")),
+        };
+        let unit = unit(
+            Path::new("/opt/my bin/tg"),
+            Some("work"),
+            &overrides,
+            Path::new("/mnt/100% tags"),
+        );
+        assert_eq!(
+            exec_start(&unit),
+            r#""/opt/my bin/tg" --profile "work" --config "/home/me/my config.toml" --db "/home/me/db.sqlite" mount "/mnt/100%% tags""#
+        );
+        assert!(unit.contains("Description=tg tag filesystem mounted to /mnt/100%% tags\n"));
+    }
+}
@@ -1,6 +1,80 @@
+use rusqlite::OptionalExtension;
+
+pub mod tmsu;
+
 /// Duplicate separator used when two files have the same name and tags.
 pub const DUP_SEP: char = '~';
 
+/// Schema changes applied in order after `migrations.sql`, tracked with `PRAGMA user_version`.
+const MIGRATIONS: &[&str] = &[
+    // 1: Last modification time of each tag
+    r#"--sql
+    ALTER TABLE Tags ADD COLUMN modified INTEGER NOT NULL DEFAULT 0;
+
+    CREATE TRIGGER IF NOT EXISTS FileTagsInsertModified
+    AFTER INSERT ON FileTags
+    FOR EACH ROW
+    BEGIN
+       UPDATE Tags SET modified = unixepoch() WHERE id = NEW.tagId;
+    END;
+
+    CREATE TRIGGER IF NOT EXISTS FileTagsDeleteModified
+    AFTER DELETE ON FileTags
+    FOR EACH ROW
+    BEGIN
+       UPDATE Tags SET modified = unixepoch() WHERE id = OLD.tagId;
+    END;
+    "#,
+    // 2: Directories that file paths can be stored relative to
+    r#"--sql
+    CREATE TABLE IF NOT EXISTS Roots (
+           id INTEGER PRIMARY KEY AUTOINCREMENT,
+           name TEXT NOT NULL UNIQUE,
+           path TEXT NOT NULL
+    );
+    "#,
+];
+
+/// Schema version of the databases created by this version of tg.
+pub const VERSION: usize = MIGRATIONS.len();
+
+pub const TABLE_EXISTS: &str = r#"--sql
+    SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1
+"#;
+
+pub const INTEGRITY_CHECK: &str = r#"--sql
+    PRAGMA integrity_check
+"#;
+
+pub fn has_table(db: &rusqlite::Connection, table: &str) -> rusqlite::Result<bool> {
+    db.query_row(TABLE_EXISTS, [table], |_| Ok(()))
+        .optional()
+        .map(|r| r.is_some())
+}
+
+/// Schema version of `db`, the number of migrations applied to it.
+pub fn version(db: &rusqlite::Connection) -> rusqlite::Result<usize> {
+    db.pragma_query_value(None, "user_version", |r| r.get(0))
+}
+
+/// Whether `db` already existed and has pending migrations.
+pub fn needs_migration(db: &rusqlite::Connection) -> rusqlite::Result<bool> {
+    Ok(has_table(db, "Files")? && version(db)? < VERSION)
+}
+
+/// Creates the database schema and applies the pending migrations.
+pub fn migrate(db: &mut rusqlite::Connection) -> rusqlite::Result<()> {
+    db.execute_batch(include_str!("./migrations.sql"))?;
+
+    let version = version(db)?;
+    let tx = db.transaction()?;
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", i + 1)?;
+    }
+    tx.commit()
+}
+
 pub const FILE_EXISTS: &str = r#"--sql
     SELECT 1 FROM Files WHERE name = ?1 LIMIT 1
 "#;
@@ -9,10 +83,18 @@ pub const TAG_EXISTS: &str = r#"--sql
     SELECT 1 FROM Tags WHERE tag = ?1 LIMIT 1
 "#;
 
+pub const TAG_MODIFIED: &str = r#"--sql
+    SELECT modified FROM Tags WHERE tag = ?1
+"#;
+
 pub const INSERT_FILE: &str = r#"--sql
     INSERT OR IGNORE INTO Files (path) VALUES (?1)
 "#;
 
+pub const SET_FILE_NAME: &str = r#"--sql
+    UPDATE Files SET name = ?2 WHERE path = ?1
+"#;
+
 pub const INSERT_TAG: &str = r#"--sql
     INSERT OR IGNORE INTO Tags (tag) VALUES (?1)
 "#;
@@ -33,14 +115,161 @@ pub const INSERT_FILETAG: &str = r#"--sql
         )
 "#;
 
+pub const DELETE_FILETAG: &str = r#"--sql
+    DELETE FROM FileTags
+     WHERE fileId = (
+               SELECT id
+                 FROM Files
+                WHERE path = ?1
+           )
+       AND tagId = (
+               SELECT id
+                 FROM Tags
+                WHERE tag = ?2
+           )
+"#;
+
+pub const GET_FILE_TAGS: &str = r#"--sql
+    SELECT t.tag
+      FROM FileTags ft
+      JOIN Tags t ON t.id = ft.tagId
+     WHERE ft.fileId = (
+               SELECT id
+                 FROM Files
+                WHERE path = ?1
+           )
+  ORDER BY t.tag
+"#;
+
+pub const SET_FILE_PATH: &str = r#"--sql
+    UPDATE Files SET path = ?2 WHERE path = ?1
+"#;
+
+pub const GET_ROOTS: &str = r#"--sql
+    SELECT id, name, path FROM Roots ORDER BY name
+"#;
+
+pub const INSERT_ROOT: &str = r#"--sql
+    INSERT INTO Roots (name, path) VALUES (?1, ?2)
+"#;
+
+pub const SET_ROOT_PATH: &str = r#"--sql
+    UPDATE Roots SET path = ?2 WHERE name = ?1
+"#;
+
+pub const DELETE_ROOT: &str = r#"--sql
+    DELETE FROM Roots WHERE name = ?1
+"#;
+
+pub const ALL_FILES: &str = r#"--sql
+    SELECT path FROM Files ORDER BY path
+"#;
+
+pub const FILE_NAME: &str = r#"--sql
+    SELECT name FROM Files WHERE path = ?1
+"#;
+
+pub const DELETE_FILE_TAGS: &str = r#"--sql
+    DELETE FROM FileTags
+     WHERE fileId = (
+               SELECT id
+                 FROM Files
+                WHERE path = ?1
+           )
+"#;
+
+pub const DELETE_FILE: &str = r#"--sql
+    DELETE FROM Files WHERE path = ?1
+"#;
+
+/// Temporary tables tracking what an import has seen, so files repeated in it aren't replaced twice
+/// and `mirror` knows what to remove.
+pub const CREATE_IMPORTED: &str = r#"--sql
+    CREATE TEMP TABLE IF NOT EXISTS ImportedFiles (path TEXT PRIMARY KEY) WITHOUT ROWID;
+    CREATE TEMP TABLE IF NOT EXISTS ImportedTags (tag TEXT PRIMARY KEY) WITHOUT ROWID;
+    DELETE FROM temp.ImportedFiles;
+    DELETE FROM temp.ImportedTags;
+"#;
+
+pub const DROP_IMPORTED: &str = r#"--sql
+    DROP TABLE IF EXISTS temp.ImportedFiles;
+    DROP TABLE IF EXISTS temp.ImportedTags;
+"#;
+
+pub const INSERT_IMPORTED_FILE: &str = r#"--sql
+    INSERT OR IGNORE INTO temp.ImportedFiles (path) VALUES (?1)
+"#;
+
+pub const INSERT_IMPORTED_TAG: &str = r#"--sql
+    INSERT OR IGNORE INTO temp.ImportedTags (tag) VALUES (?1)
+"#;
+
+pub const NOT_IMPORTED_FILES: &str = r#"--sql
+    SELECT path
+      FROM Files
+     WHERE path NOT IN (SELECT path FROM temp.ImportedFiles)
+  ORDER BY path
+"#;
+
+pub const DELETE_NOT_IMPORTED_TAGS: &str = r#"--sql
+    DELETE FROM Tags
+     WHERE id NOT IN (SELECT tagId FROM FileTags)
+       AND tag NOT IN (SELECT tag FROM temp.ImportedTags)
+"#;
+
+pub const EXPORT_TAGS: &str = r#"--sql
+    SELECT t.tag, t.modified, COUNT(ft.fileId)
+      FROM Tags t
+      LEFT JOIN FileTags ft ON ft.tagId = t.id
+  GROUP BY t.id
+  ORDER BY t.tag
+"#;
+
+/// Rows of (id, path, name, tag) of every file tagged with all `tags` tags, passed as parameters,
+/// ordered by file so they can be grouped while streaming.
+///
+/// Files without tags are returned with a `NULL` tag if `tags` is 0.
+pub fn export_files(tags: usize) -> String {
+    let filter = if tags == 0 {
+        String::new()
+    } else {
+        let params = vec!["?"; tags].join(",");
+        format!(
+            r#"WHERE f.id IN (
+                SELECT ft.fileId
+                  FROM FileTags ft
+                  JOIN Tags t ON t.id = ft.tagId
+                 WHERE t.tag IN ({params})
+              GROUP BY ft.fileId
+                HAVING COUNT(*) = {tags}
+            )"#
+        )
+    };
+    format!(
+        r#"--sql
+        SELECT f.id, f.path, f.name, t.tag
+          FROM Files f
+          LEFT JOIN FileTags ft ON ft.fileId = f.id
+          LEFT JOIN Tags t ON t.id = ft.tagId
+        {filter}
+      ORDER BY f.id
+    "#
+    )
+}
+
+pub const COUNT_FILES_TAGS: &str = r#"--sql
+    SELECT (SELECT COUNT(*) FROM Files), (SELECT COUNT(*) FROM Tags)
+"#;
+
 pub const GET_USED_TAGS: &str = r#"--sql
     SELECT DISTINCT t.tag
       FROM Tags t
       JOIN FileTags ft ON ft.tagId = t.id;
 "#;
 
-pub fn matching_tags_files<D: std::fmt::Debug>(tags: impl IntoIterator<Item = D>) -> String {
-    let tags_list = crate::utils::list_to_values(tags);
+/// Tags and files inside the directory of `tags` tags, bound as parameters in any order.
+pub fn matching_tags_files(tags: usize) -> String {
+    let tags_list = vec!["(?)"; tags].join(",");
     format!(
         r#"--sql 
         -- Step 1: Define the target tags to match
@@ -76,6 +305,7 @@ pub fn matching_tags_files<D: std::fmt::Debug>(tags: impl IntoIterator<Item = D>
         -- Step 5: Check for duplicates and adjust file names if necessary
             DuplicateCheck AS MATERIALIZED (
                 SELECT f.id,
+                    f.path,
                     f.name,
                     CASE
                         -- Check if there's another file with the same name
@@ -98,11 +328,11 @@ pub fn matching_tags_files<D: std::fmt::Debug>(tags: impl IntoIterator<Item = D>
             )
             -- Step 6: Combine and return the results
             -- Return found tags
-            SELECT t.tag, NULL AS file
+            SELECT t.tag, NULL AS file, NULL AS id, NULL AS path
             FROM FoundTags t
             UNION ALL
             -- Return found files with adjusted names
-            SELECT NULL AS tag, dc.adjusted_name AS file
+            SELECT NULL AS tag, dc.adjusted_name AS file, dc.id, dc.path
             FROM DuplicateCheck dc
     "#
     )
@@ -0,0 +1,42 @@
+//! Queries reading a TMSU database, whose schema is described in TMSU's `storage/database/schema.go`.
+
+/// Whether implications have values, added in TMSU 0.6.
+pub const IMPLICATION_HAS_VALUES: &str = r#"--sql
+    SELECT 1 FROM pragma_table_info('implication') WHERE name = 'value_id'
+"#;
+
+pub const TAGS: &str = r#"--sql
+    SELECT name FROM tag ORDER BY name
+"#;
+
+/// Rows of (id, directory, name, tag, value) ordered by file, the value 0 means the tag has none.
+pub const FILE_TAGS: &str = r#"--sql
+    SELECT f.id, f.directory, f.name, t.name, v.name
+      FROM file f
+      LEFT JOIN file_tag ft ON ft.file_id = f.id
+      LEFT JOIN tag t ON t.id = ft.tag_id
+      LEFT JOIN value v ON v.id = ft.value_id
+  ORDER BY f.id
+"#;
+
+/// Rows of (tag, value, implied tag, implied value).
+pub const IMPLICATIONS: &str = r#"--sql
+    SELECT t.name, v.name, it.name, iv.name
+      FROM implication i
+      JOIN tag t ON t.id = i.tag_id
+      LEFT JOIN value v ON v.id = i.value_id
+      JOIN tag it ON it.id = i.implied_tag_id
+      LEFT JOIN value iv ON iv.id = i.implied_value_id
+"#;
+
+/// Implications of TMSU versions before 0.6, without values.
+pub const IMPLICATIONS_NO_VALUES: &str = r#"--sql
+    SELECT t.name, NULL, it.name, NULL
+      FROM implication i
+      JOIN tag t ON t.id = i.tag_id
+      JOIN tag it ON it.id = i.implied_tag_id
+"#;
+
+pub const QUERIES: &str = r#"--sql
+    SELECT text FROM query ORDER BY text
+"#;
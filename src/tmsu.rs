@@ -0,0 +1,348 @@
+use anyhow::{bail, Context, Result};
+use rusqlite::OptionalExtension;
+use std::{
+    collections::{BTreeSet, HashMap},
+    io::Write,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{
+    export::{File, Tag},
+    import::{Entry, Options, Summary},
+    sql::{has_table, tmsu as sql},
+};
+
+/// A TMSU tag of a file and its value, if any.
+type Pair = (String, Option<String>);
+
+/// Result of a TMSU import.
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    pub summary: Summary,
+    /// Files, tags, values and queries that could not be converted, with the reason
+    pub unconverted: Vec<String>,
+    /// Number of tags added to the files because of an implication
+    pub implied: usize,
+}
+
+/// The database TMSU would use from `start`, `.tmsu/db` in it or its parents or `~/.tmsu/default.db`.
+pub fn find(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .map(|dir| dir.join(".tmsu").join("db"))
+        .chain(directories::BaseDirs::new().map(|d| d.home_dir().join(".tmsu").join("default.db")))
+        .find(|path| path.is_file())
+}
+
+/// Directory that relative paths in the database at `path` are relative to, the parent of `.tmsu`.
+fn root(path: &Path) -> PathBuf {
+    let dir = path.parent().unwrap_or(Path::new("/"));
+    match dir.file_name() {
+        Some(name) if name == ".tmsu" => dir.parent().unwrap_or(dir).to_path_buf(),
+        _ => dir.to_path_buf(),
+    }
+}
+
+/// Converts TMSU tags to tg tags, collecting what can't be converted.
+struct Converter {
+    /// Tags implied by each tag, a `None` value applies to all the values of the tag
+    implications: HashMap<Pair, Vec<Pair>>,
+    /// Number of converted tags that were only implied
+    implied: usize,
+    unconverted: BTreeSet<String>,
+}
+
+impl Converter {
+    fn tag(&mut self, tag: &str) -> Option<String> {
+        if tag.is_empty() || tag.contains('/') {
+            self.unconverted
+                .insert(format!("tag {tag:?}, tags cannot be empty or contain '/'"));
+            return None;
+        }
+        Some(tag.to_string())
+    }
+
+    /// The tags of `pairs` and the ones they imply, values are added as `tag=value`.
+    fn tags(&mut self, mut pairs: BTreeSet<Pair>) -> Vec<String> {
+        let explicit = pairs.clone();
+        let mut pending = pairs.iter().cloned().collect::<Vec<_>>();
+        while let Some((tag, value)) = pending.pop() {
+            let any_value = value.is_some().then(|| (tag.clone(), None));
+            let implied = [Some((tag, value)), any_value]
+                .into_iter()
+                .flatten()
+                .filter_map(|pair| self.implications.get(&pair))
+                .flatten();
+            for pair in implied {
+                if pairs.insert(pair.clone()) {
+                    pending.push(pair.clone());
+                }
+            }
+        }
+
+        let mut tags = Vec::with_capacity(pairs.len());
+        for pair in pairs {
+            let implied = !explicit.contains(&pair);
+            let (tag, value) = pair;
+            let Some(tag) = self.tag(&tag) else {
+                continue;
+            };
+            match value {
+                Some(value) if value.contains('/') => {
+                    self.unconverted
+                        .insert(format!("value {tag}={value:?}, values cannot contain '/'"));
+                }
+                Some(value) => tags.push(format!("{tag}={value}")),
+                None => {}
+            }
+            tags.push(tag);
+            self.implied += usize::from(implied);
+        }
+        tags.sort_unstable();
+        tags.dedup();
+        tags
+    }
+
+    /// The tg file of `path`, `None` if its name can't be displayed by tg.
+    fn file(&mut self, path: PathBuf, pairs: BTreeSet<Pair>) -> Option<File> {
+        let name = path.file_name()?.to_string_lossy();
+        if let Err(e) = crate::db::validate_name(&name) {
+            self.unconverted.insert(format!("file {path:?}, {e}"));
+            return None;
+        }
+        Some(File {
+            tags: self.tags(pairs),
+            path,
+            name: None,
+        })
+    }
+}
+
+fn implications(tmsu: &rusqlite::Connection) -> Result<HashMap<Pair, Vec<Pair>>> {
+    let mut implications = HashMap::<Pair, Vec<Pair>>::new();
+    if !has_table(tmsu, "implication")? {
+        return Ok(implications);
+    }
+    let has_values = tmsu
+        .query_row(sql::IMPLICATION_HAS_VALUES, [], |_| Ok(()))
+        .optional()?
+        .is_some();
+    let query = match has_values {
+        true => sql::IMPLICATIONS,
+        false => sql::IMPLICATIONS_NO_VALUES,
+    };
+    let mut stmt = tmsu.prepare(query)?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        implications
+            .entry((row.get(0)?, row.get(1)?))
+            .or_default()
+            .push((row.get(2)?, row.get(3)?));
+    }
+    Ok(implications)
+}
+
+/// Merges the files and tags of the TMSU database at `tmsu_path` into the database at `db_path`
+/// like [`crate::import::import`].
+///
+/// tg has no values or implications, so each value becomes a `tag=value` tag added next to `tag`
+/// and implied tags are added to the files directly.
+pub fn import(
+    db: &mut rusqlite::Connection,
+    db_path: &Path,
+    tmsu_path: &Path,
+    options: &Options,
+    diff: Option<&mut dyn Write>,
+) -> Result<Report> {
+    let tmsu = rusqlite::Connection::open_with_flags(
+        tmsu_path,
+        rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY,
+    )
+    .with_context(|| format!("could not open {tmsu_path:?}"))?;
+    if !has_table(&tmsu, "file_tag")? {
+        bail!("{tmsu_path:?} is not a TMSU database");
+    }
+    let root = root(&std::path::absolute(tmsu_path)?);
+    convert(db, db_path, &tmsu, &root, options, diff)
+}
+
+/// Imports the opened TMSU database `tmsu`, with relative paths relative to `root`.
+fn convert(
+    db: &mut rusqlite::Connection,
+    db_path: &Path,
+    tmsu: &rusqlite::Connection,
+    root: &Path,
+    options: &Options,
+    diff: Option<&mut dyn Write>,
+) -> Result<Report> {
+    let mut converter = Converter {
+        implications: implications(tmsu)?,
+        implied: 0,
+        unconverted: BTreeSet::new(),
+    };
+
+    let summary = crate::import::import_entries(db, db_path, options, diff, false, |f| {
+        let mut stmt = tmsu.prepare(sql::TAGS)?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            if let Some(tag) = converter.tag(&row.get::<_, String>(0)?) {
+                f(Entry::Tag(Tag {
+                    tag,
+                    modified: 0,
+                    files: 0,
+                }))?;
+            }
+        }
+
+        let mut stmt = tmsu.prepare(sql::FILE_TAGS)?;
+        let mut rows = stmt.query([])?;
+        let mut current: Option<(i64, PathBuf, BTreeSet<Pair>)> = None;
+        while let Some(row) = rows.next()? {
+            let id: i64 = row.get(0)?;
+            let pair = match row.get::<_, Option<String>>(3)? {
+                Some(tag) => Some((tag, row.get(4)?)),
+                None => None,
+            };
+            match &mut current {
+                Some((current_id, _, pairs)) if *current_id == id => pairs.extend(pair),
+                _ => {
+                    // Paths are relative to the root since TMSU 0.7
+                    let path = root
+                        .join(row.get::<_, String>(1)?)
+                        .join(row.get::<_, String>(2)?)
+                        .components()
+                        .filter(|c| *c != Component::CurDir)
+                        .collect::<PathBuf>();
+                    let next = (id, path, pair.into_iter().collect());
+                    if let Some((_, path, pairs)) = current.replace(next) {
+                        if let Some(file) = converter.file(path, pairs) {
+                            f(Entry::File(file))?;
+                        }
+                    }
+                }
+            }
+        }
+        if let Some((_, path, pairs)) = current {
+            if let Some(file) = converter.file(path, pairs) {
+                f(Entry::File(file))?;
+            }
+        }
+        Ok(())
+    })?;
+
+    if has_table(tmsu, "query")? {
+        let mut stmt = tmsu.prepare(sql::QUERIES)?;
+        let queries = stmt.query_map([], |r| r.get::<_, String>(0))?;
+        for query in queries {
+            converter
+                .unconverted
+                .insert(format!("saved query {:?}, tg has no saved queries", query?));
+        }
+    }
+    Ok(Report {
+        summary,
+        implied: converter.implied,
+        unconverted: converter.unconverted.into_iter().collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::import::Mode;
+
+    /// TMSU database with values, implications and what tg can't convert.
+    fn fixture() -> rusqlite::Connection {
+        let tmsu = rusqlite::Connection::open_in_memory().unwrap();
+        tmsu.execute_batch(
+            r#"
+            CREATE TABLE tag (id INTEGER PRIMARY KEY, name TEXT NOT NULL);
+            CREATE TABLE value (id INTEGER PRIMARY KEY, name TEXT NOT NULL);
+            CREATE TABLE file (id INTEGER PRIMARY KEY, directory TEXT NOT NULL, name TEXT NOT NULL);
+            CREATE TABLE file_tag (file_id INTEGER, tag_id INTEGER, value_id INTEGER);
+            CREATE TABLE implication (
+                tag_id INTEGER, value_id INTEGER, implied_tag_id INTEGER, implied_value_id INTEGER
+            );
+            CREATE TABLE query (text TEXT PRIMARY KEY);
+
+            INSERT INTO tag VALUES
+                (1, 'music'), (2, 'genre'), (3, 'audio'), (4, 'media'), (5, 'a/b'), (6, 'sorted');
+            INSERT INTO value VALUES (1, 'rock'), (2, 'x/y');
+            INSERT INTO file VALUES (1, '.', 'song.mp3'), (2, '/abs', 'clip'), (3, 'sub', 'plain');
+            INSERT INTO file_tag VALUES (1, 1, 0), (1, 2, 1), (1, 5, 0), (2, 2, 2);
+            -- genre=rock => music => audio => media, genre with any value => sorted
+            INSERT INTO implication VALUES (2, 1, 1, 0), (1, 0, 3, 0), (3, 0, 4, 0), (2, 0, 6, 0);
+            INSERT INTO query VALUES ('music and audio');
+            "#,
+        )
+        .unwrap();
+        tmsu
+    }
+
+    #[test]
+    fn import_fixture() {
+        let mut db = rusqlite::Connection::open_in_memory().unwrap();
+        crate::sql::migrate(&mut db).unwrap();
+        let options = Options {
+            mode: Mode::Add,
+            rewrites: Vec::new(),
+            dry_run: false,
+        };
+        let report = convert(
+            &mut db,
+            Path::new(":memory:"),
+            &fixture(),
+            Path::new("/tmsu"),
+            &options,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(
+            report.summary,
+            Summary {
+                added: 3,
+                tags_added: 6,
+                ..Default::default()
+            }
+        );
+        // audio, media and sorted for the song, sorted for the clip
+        assert_eq!(report.implied, 4);
+        assert_eq!(
+            report.unconverted,
+            [
+                r#"saved query "music and audio", tg has no saved queries"#,
+                r#"tag "a/b", tags cannot be empty or contain '/'"#,
+                r#"value genre="x/y", values cannot contain '/'"#,
+            ]
+        );
+
+        let tags = |path: &str| crate::db::file_tags(&db, path).unwrap();
+        assert_eq!(
+            tags("/tmsu/song.mp3"),
+            ["audio", "genre", "genre=rock", "media", "music", "sorted"]
+        );
+        assert_eq!(tags("/abs/clip"), ["genre", "sorted"]);
+        assert!(tags("/tmsu/sub/plain").is_empty());
+        assert_eq!(crate::db::files(&db, &[]).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn implications_without_values() {
+        let tmsu = rusqlite::Connection::open_in_memory().unwrap();
+        tmsu.execute_batch(
+            r#"
+            CREATE TABLE tag (id INTEGER PRIMARY KEY, name TEXT NOT NULL);
+            CREATE TABLE implication (tag_id INTEGER, implied_tag_id INTEGER);
+            INSERT INTO tag VALUES (1, 'a'), (2, 'b');
+            INSERT INTO implication VALUES (1, 2);
+            "#,
+        )
+        .unwrap();
+        let implications = implications(&tmsu).unwrap();
+        assert_eq!(
+            implications[&("a".to_string(), None)],
+            [("b".to_string(), None)]
+        );
+    }
+}
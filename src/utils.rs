@@ -15,20 +15,6 @@ impl log::Log for ConsoleLogger {
 #[allow(private_interfaces)]
 pub static LOGGER: ConsoleLogger = ConsoleLogger;
 
-pub fn list_to_values<S: std::fmt::Debug>(list: impl IntoIterator<Item = S>) -> String {
-    let mut list = list.into_iter();
-
-    let mut acc = String::from("(");
-    if let Some(f) = list.next() {
-        acc = format!("{acc}{f:?})");
-    }
-    for s in list {
-        acc = format!("{acc},({s:?})");
-    }
-
-    acc
-}
-
 pub fn list_to_values_and_key(key: impl std::fmt::Debug, list: impl AsRef<[String]>) -> String {
     let list = list.as_ref();
 
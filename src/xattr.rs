@@ -0,0 +1,273 @@
+use anyhow::{Context, Result};
+use std::{
+    ffi::CString,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+use crate::{roots::Roots, sql};
+
+/// Freedesktop extended attribute with the comma separated tags of a file.
+pub const XDG_TAGS: &str = "user.xdg.tags";
+
+/// A file whose tags differ between the database and its extended attributes.
+#[derive(Debug)]
+pub struct Conflict {
+    pub path: PathBuf,
+    /// Tags only present in the database
+    pub db_only: Vec<String>,
+    /// Tags only present in the extended attribute
+    pub xattr_only: Vec<String>,
+}
+
+/// A file or directory whose tags could not be synced.
+#[derive(Debug)]
+pub struct Skipped {
+    pub path: PathBuf,
+    pub error: anyhow::Error,
+}
+
+/// Result of syncing the tags of a directory.
+#[derive(Debug, Default)]
+pub struct Report {
+    pub conflicts: Vec<Conflict>,
+    pub skipped: Vec<Skipped>,
+}
+
+fn c_path(path: &Path) -> std::io::Result<CString> {
+    CString::new(path.as_os_str().as_bytes()).map_err(std::io::Error::other)
+}
+
+/// Reads the `user.xdg.tags` of `path`, returning an empty list if not set.
+pub fn get_tags(path: impl AsRef<Path>) -> std::io::Result<Vec<String>> {
+    let path = c_path(path.as_ref())?;
+    let name = CString::new(XDG_TAGS).unwrap();
+
+    let len = unsafe { libc::getxattr(path.as_ptr(), name.as_ptr(), std::ptr::null_mut(), 0) };
+    if len < 0 {
+        let e = std::io::Error::last_os_error();
+        return match e.raw_os_error() {
+            Some(libc::ENODATA) => Ok(Vec::new()),
+            _ => Err(e),
+        };
+    }
+    let mut buf = vec![0u8; len as usize];
+    let len = unsafe {
+        libc::getxattr(
+            path.as_ptr(),
+            name.as_ptr(),
+            buf.as_mut_ptr().cast(),
+            buf.len(),
+        )
+    };
+    if len < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    buf.truncate(len as usize);
+
+    Ok(String::from_utf8_lossy(&buf)
+        .split(',')
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .map(String::from)
+        .collect())
+}
+
+/// Replaces the `user.xdg.tags` of `path` with `tags`.
+pub fn set_tags(path: impl AsRef<Path>, tags: &[impl AsRef<str>]) -> std::io::Result<()> {
+    let path = c_path(path.as_ref())?;
+    let name = CString::new(XDG_TAGS).unwrap();
+    let value = tags.iter().map(AsRef::as_ref).collect::<Vec<_>>().join(",");
+
+    let res = unsafe {
+        libc::setxattr(
+            path.as_ptr(),
+            name.as_ptr(),
+            value.as_ptr().cast(),
+            value.len(),
+            0,
+        )
+    };
+    if res < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Adds `tags` to the `user.xdg.tags` of `path`, keeping the existing ones.
+pub fn add_tags(path: impl AsRef<Path>, tags: &[impl AsRef<str>]) -> std::io::Result<()> {
+    let path = path.as_ref();
+    let mut current = get_tags(path)?;
+    for tag in tags {
+        if !current.iter().any(|t| t == tag.as_ref()) {
+            current.push(tag.as_ref().to_string());
+        }
+    }
+    set_tags(path, &current)
+}
+
+/// Calls `f` for every regular file under `dir`, without following symlinks.
+///
+/// Files for which `f` fails and subdirectories that can't be read are added to `skipped`.
+fn walk(
+    dir: &Path,
+    skipped: &mut Vec<Skipped>,
+    f: &mut impl FnMut(&Path) -> Result<()>,
+) -> Result<()> {
+    for entry in std::fs::read_dir(dir).with_context(|| format!("could not read {dir:?}"))? {
+        let (path, kind) = match entry.and_then(|e| Ok((e.path(), e.file_type()?))) {
+            Ok(entry) => entry,
+            Err(e) => {
+                skipped.push(Skipped {
+                    path: dir.to_path_buf(),
+                    error: anyhow::Error::new(e).context(format!("could not read {dir:?}")),
+                });
+                continue;
+            }
+        };
+        let walked = if kind.is_dir() {
+            walk(&path, skipped, f)
+        } else if kind.is_file() {
+            f(&path)
+        } else {
+            Ok(())
+        };
+        if let Err(error) = walked {
+            skipped.push(Skipped { path, error });
+        }
+    }
+    Ok(())
+}
+
+fn difference(a: &[String], b: &[String]) -> Vec<String> {
+    a.iter().filter(|t| !b.contains(t)).cloned().collect()
+}
+
+/// Adds the `user.xdg.tags` of every file under `dir` to the database at `db_path`.
+///
+/// Tags only present in the database are kept and reported as conflicts,
+/// files whose tags can't be read or added are skipped.
+pub fn import(
+    db: &mut rusqlite::Connection,
+    db_path: &Path,
+    dir: impl AsRef<Path>,
+) -> Result<Report> {
+    let dir = dir.as_ref().canonicalize()?;
+    let roots = Roots::load_for(db, db_path)?;
+    let mut report = Report::default();
+
+    walk(&dir, &mut report.skipped, &mut |path| {
+        let xattr = get_tags(path).context("could not read the extended attribute")?;
+        if xattr.is_empty() {
+            return Ok(());
+        }
+        let stored = crate::db::file_tags(db, &roots.store(path))?;
+        crate::db::add_with(db, &roots, path, difference(&xattr, &stored), None)?;
+
+        let db_only = difference(&stored, &xattr);
+        if !db_only.is_empty() {
+            report.conflicts.push(Conflict {
+                path: path.to_path_buf(),
+                db_only,
+                xattr_only: Vec::new(),
+            });
+        }
+        Ok(())
+    })?;
+
+    Ok(report)
+}
+
+/// Adds the tags of every file under `dir` registered in the database at `db_path`
+/// to its `user.xdg.tags`.
+///
+/// Tags only present in the extended attribute are kept and reported as conflicts,
+/// files whose attribute can't be read or written are skipped.
+pub fn export(db: &rusqlite::Connection, db_path: &Path, dir: impl AsRef<Path>) -> Result<Report> {
+    let dir = dir.as_ref().canonicalize()?;
+    let roots = Roots::load_for(db, db_path)?;
+    let mut report = Report::default();
+
+    let mut stmt = db.prepare(sql::ALL_FILES)?;
+    let paths = stmt
+        .query_map([], |r| r.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let paths = paths.into_iter().filter_map(|stored| {
+        let path = roots.resolve(&stored)?;
+        path.starts_with(&dir).then_some((stored, path))
+    });
+    for (key, path) in paths {
+        let stored = crate::db::file_tags(db, &key)?;
+        let xattr = get_tags(&path)
+            .context("could not read the extended attribute")
+            .and_then(|xattr| {
+                add_tags(&path, &stored).context("could not write the extended attribute")?;
+                Ok(xattr)
+            });
+        let xattr = match xattr {
+            Ok(xattr) => xattr,
+            Err(error) => {
+                report.skipped.push(Skipped { path, error });
+                continue;
+            }
+        };
+
+        let xattr_only = difference(&xattr, &stored);
+        if !xattr_only.is_empty() {
+            report.conflicts.push(Conflict {
+                path,
+                db_only: Vec::new(),
+                xattr_only,
+            });
+        }
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sync_project_and_skip_missing_files() -> Result<()> {
+        let root = std::env::temp_dir().join(format!("tg-xattr-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join(crate::project::DIR))?;
+        std::fs::create_dir_all(root.join("sub"))?;
+        let root = root.canonicalize()?;
+        let db_path = root.join(crate::project::DIR).join("db.sqlite");
+        let mut db = rusqlite::Connection::open(&db_path)?;
+        sql::migrate(&mut db)?;
+
+        let (a, b) = (root.join("sub").join("a"), root.join("sub").join("b"));
+        std::fs::write(&a, "")?;
+        std::fs::write(&b, "")?;
+        crate::db::add_relative(&mut db, &root, &a, ["x".to_string()], None)?;
+        set_tags(&b, &["y", "z"])?;
+
+        let report = import(&mut db, &db_path, &root)?;
+        assert!(report.conflicts.is_empty() && report.skipped.is_empty());
+        assert_eq!(crate::db::file_tags(&db, "sub/b")?, ["y", "z"]);
+
+        std::fs::remove_file(&a)?;
+        std::fs::write(root.join("sub").join("c"), "")?;
+        crate::db::add_relative(
+            &mut db,
+            &root,
+            root.join("sub").join("c"),
+            ["w".to_string()],
+            None,
+        )?;
+        set_tags(root.join("sub").join("c"), &["v"])?;
+        let report = export(&db, &db_path, &root)?;
+        std::fs::remove_dir_all(&root)?;
+
+        let skipped = report.skipped.iter().map(|s| &s.path).collect::<Vec<_>>();
+        assert_eq!(skipped, [&a]);
+        assert_eq!(report.conflicts.len(), 1);
+        assert_eq!(report.conflicts[0].path, root.join("sub").join("c"));
+        assert_eq!(report.conflicts[0].xattr_only, ["v"]);
+        Ok(())
+    }
+}